@@ -1,23 +1,46 @@
 pub mod services;
 
 use bassbox_core::graph::new_shared_graph;
-use bassbox_core::engine::{AudioEngine, speaker::SpeakerEngine};
+use bassbox_core::engine::{AudioEngine, ControlMsg, speaker::{self, SpeakerEngine}, file::FileRenderEngine, null::NullEngine, pipe::{PipeEngine, PipeOutput, PcmFormat}};
 use getopts::Options;
-use bassbox_rpc_api::AudioGraphServiceRpc;
-use services::graph::AudioGraphService;
+use bassbox_rpc_api::{AudioGraphServiceRpc, EngineServiceRpc};
+use services::graph::{AudioGraphService, DEFAULT_HISTORY_CAPACITY};
+use services::engine::{EngineService, SharedEngine};
 use std::env;
+use std::process;
+#[cfg(feature = "jack")]
+use bassbox_core::engine::jack::JackEngine;
+use std::path::PathBuf;
 use jsonrpc_core::IoHandler;
-use jsonrpc_stdio_server::ServerBuilder;
+use parking_lot::RwLock;
+use std::sync::Arc;
+use jsonrpc_stdio_server::ServerBuilder as StdioServerBuilder;
+use jsonrpc_tcp_server::ServerBuilder as TcpServerBuilder;
 
-fn print_usage(program: &str, opts: Options) {
+/// Reports invalid arguments along with the usage
+/// on stderr (since stdout may be used for RPC)
+/// and exits.
+fn exit_with_usage(message: &str, program: &str, opts: &Options) -> ! {
 	let brief = format!("Usage: {} [--engine ENGINE] [options]", program);
-	print!("{}", opts.usage(&brief));
+	eprintln!("{}", message);
+	eprint!("{}", opts.usage(&brief));
+	process::exit(2);
 }
 
-// TODO: Support HTTP/MPSC as an alternative to Stdio
+fn print_devices() {
+	for host in speaker::list_devices() {
+		println!("{}{}", host.name, if host.is_default { " (default)" } else { "" });
+		for device in host.devices {
+			println!("  {}{}", device.name, if device.is_default { " (default)" } else { "" });
+			for format in device.formats {
+				println!("    {} channels, {} - {} Hz, {}", format.channels, format.min_sample_hz, format.max_sample_hz, format.sample_format);
+			}
+		}
+	}
+}
 
 fn main() {
-	let supported_engines = ["speaker"];
+	let supported_engines = ["speaker", "file", "null", "pipe", #[cfg(feature = "jack")] "jack"];
 
 	// Parse CLI args
 	let args: Vec<String> = env::args().collect();
@@ -26,27 +49,162 @@ fn main() {
 	let mut opts = Options::new();
 	opts.optopt("e", "engine", "Specifies which audio output is used", format!("[{}]", supported_engines.join("|")).as_str());
 	opts.optopt("t", "token", "Optionally provides an authentication token if required by the engine", "TOKEN");
+	opts.optopt("o", "output", "The output file of the file engine (which starts paused until engine.play or --play) or the output of the pipe engine (- for stdout)", "PATH");
+	opts.optopt("r", "sample-rate", "The output sample rate (defaults to 44100 or the device's default)", "HZ");
+	opts.optopt("b", "bit-depth", "The output bit depth of the file engine (defaults to 16)", "[16|24|32]");
+	opts.optopt("d", "duration", "The maximum duration rendered by the file engine", "SECS");
+	opts.optopt("", "host", "The audio host used by the speaker engine", "NAME");
+	opts.optopt("", "device", "The output device used by the speaker engine", "NAME");
+	opts.optopt("", "channel-map", "Routes the stereo output onto the speaker engine's device channels, e.g. L,R,L,R (use M for a mono mix and - for silence)", "ROUTES");
+	opts.optflag("", "dither", "Applies TPDF dither when the speaker engine outputs 16-bit samples");
+	opts.optopt("", "client-name", "The client name registered by the JACK engine (defaults to bassbox)", "NAME");
+	opts.optflag("", "auto-connect", "Connects the JACK engine's ports to the system playback ports");
+	opts.optopt("", "pcm-format", "The sample format written by the pipe engine (defaults to f32le)", "[f32le|s16le]");
+	opts.optflag("", "realtime", "Paces the pipe engine's output to real time");
+	opts.optopt("", "rpc-transport", "The transport used by the JSON-RPC server (defaults to stdio)", "[stdio|tcp]");
+	opts.optopt("", "rpc-address", "The address the TCP JSON-RPC server listens on (defaults to 127.0.0.1:7878)", "ADDRESS");
+	opts.optopt("", "load", "Loads a project file saved via audioGraph.save on startup (implies --play)", "PATH");
+	opts.optopt("", "history-size", format!("The number of edits that can be undone, which keep removed files and commands open (defaults to {})", DEFAULT_HISTORY_CAPACITY).as_str(), "EDITS");
+	opts.optflag("", "play", "Starts playing right away, even if the engine starts paused (like the file engine)");
+	opts.optflag("l", "list-devices", "Lists the available hosts, output devices and formats");
+	opts.optopt("s", "speed", "The playback speed of the null engine relative to real time (defaults to 1)", "FACTOR");
+	
+	let parsed_args = opts.parse(&args[1..]).unwrap_or_else(|e| exit_with_usage(&e.to_string(), &program, &opts));
+	if parsed_args.opt_present("list-devices") {
+		print_devices();
+		return;
+	}
 	
-	let parsed_args = opts.parse(&args[1..]).unwrap();
 	let engine_str = match parsed_args.opt_str("engine") {
 		Some(s) => s,
-		None => {
-			println!("Missing engine.");
-			print_usage(&program, opts);
-			return;
-		}
+		None => exit_with_usage("Missing engine.", &program, &opts)
 	};
 	
+	let rpc_transport = parsed_args.opt_str("rpc-transport").unwrap_or_else(|| "stdio".to_owned());
+	let history_capacity = parsed_args.opt_get_default("history-size", DEFAULT_HISTORY_CAPACITY).unwrap_or_else(|e| exit_with_usage(&format!("Invalid history size: {}", e), &program, &opts));
+	
 	// Spawn engine
-	let shared_graph = new_shared_graph();
-	let background_engine = match engine_str.as_str() {
-		"speaker" => SpeakerEngine.run_async(shared_graph.clone()),
-		_ => panic!("Unrecognized engine, try one of these: {:?}.", supported_engines)
+	let (shared_graph, renderer) = new_shared_graph();
+	// The speaker engine can be restarted on another output
+	let mut speaker_config = None;
+	let started = match engine_str.as_str() {
+		"speaker" => {
+			let engine = SpeakerEngine {
+				host: parsed_args.opt_str("host"),
+				device: parsed_args.opt_str("device"),
+				sample_hz: parsed_args.opt_get("sample-rate").unwrap_or_else(|e| exit_with_usage(&format!("Invalid sample rate: {}", e), &program, &opts)),
+				channel_map: parsed_args.opt_get("channel-map").unwrap_or_else(|e| exit_with_usage(&format!("Invalid channel map: {}", e), &program, &opts)),
+				dither: parsed_args.opt_present("dither")
+			};
+			speaker_config = Some(engine.clone());
+			engine.run_async(renderer)
+		},
+		"file" => {
+			let output = match parsed_args.opt_str("output") {
+				Some(s) => PathBuf::from(s),
+				None => exit_with_usage("Missing output file.", &program, &opts)
+			};
+			let sample_hz = parsed_args.opt_get_default("sample-rate", 44100.0).unwrap_or_else(|e| exit_with_usage(&format!("Invalid sample rate: {}", e), &program, &opts));
+			let bit_depth = parsed_args.opt_get_default("bit-depth", 16).unwrap_or_else(|e| exit_with_usage(&format!("Invalid bit depth: {}", e), &program, &opts));
+			let duration_secs = parsed_args.opt_get("duration").unwrap_or_else(|e| exit_with_usage(&format!("Invalid duration: {}", e), &program, &opts));
+			let engine = FileRenderEngine::new(output, sample_hz, bit_depth, duration_secs).unwrap_or_else(|e| exit_with_usage(&e, &program, &opts));
+			engine.run_async(renderer)
+		},
+		"null" => {
+			let sample_hz = parsed_args.opt_get_default("sample-rate", 44100.0).unwrap_or_else(|e| exit_with_usage(&format!("Invalid sample rate: {}", e), &program, &opts));
+			let speed = parsed_args.opt_get_default("speed", 1.0).unwrap_or_else(|e| exit_with_usage(&format!("Invalid speed: {}", e), &program, &opts));
+			let engine = NullEngine::new(sample_hz, speed).unwrap_or_else(|e| exit_with_usage(&e, &program, &opts));
+			engine.run_async(renderer)
+		},
+		"pipe" => {
+			let output = match parsed_args.opt_str("output").as_ref().map(|s| s.as_str()) {
+				None | Some("-") => PipeOutput::Stdout,
+				Some(path) => PipeOutput::Path(PathBuf::from(path))
+			};
+			if let (PipeOutput::Stdout, "stdio") = (&output, rpc_transport.as_str()) {
+				exit_with_usage("The pipe engine can not write to stdout while it is used for RPC, try --rpc-transport tcp.", &program, &opts);
+			}
+			let engine = PipeEngine {
+				output: output,
+				format: parsed_args.opt_get_default("pcm-format", PcmFormat::F32Le).unwrap_or_else(|e| exit_with_usage(&format!("Invalid PCM format: {}", e), &program, &opts)),
+				sample_hz: parsed_args.opt_get_default("sample-rate", 44100.0).unwrap_or_else(|e| exit_with_usage(&format!("Invalid sample rate: {}", e), &program, &opts)),
+				realtime: parsed_args.opt_present("realtime")
+			};
+			engine.run_async(renderer)
+		},
+		#[cfg(feature = "jack")]
+		"jack" => {
+			let mut engine = JackEngine::default();
+			if let Some(client_name) = parsed_args.opt_str("client-name") {
+				engine.client_name = client_name;
+			}
+			engine.auto_connect = parsed_args.opt_present("auto-connect");
+			engine.run_async(renderer)
+		},
+		_ => exit_with_usage(&format!("Unrecognized engine, try one of these: {:?}.", supported_engines), &program, &opts)
+	};
+	let background_engine: SharedEngine = match started {
+		Ok(engine) => Arc::new(RwLock::new(engine)),
+		Err(e) => {
+			eprintln!("Could not start the {} engine: {}", engine_str, e);
+			process::exit(1);
+		}
 	};
 
 	// Setup RPC server
 	let mut io = IoHandler::new();
-	io.extend_with(AudioGraphService::using_graph(shared_graph, background_engine.clone()).to_delegate());
+	let graph_service = AudioGraphService::using_graph(shared_graph.clone(), background_engine.clone())
+		.with_history_capacity(history_capacity);
+	let edit_history = graph_service.edit_history();
+	if let Some(path) = parsed_args.opt_str("load") {
+		// Stdout may be used for RPC, thus we report to stderr
+		let result = graph_service.load(path).unwrap_or_else(|e| {
+			eprintln!("Could not load project: {}", e.message);
+			process::exit(1);
+		});
+		for failure in result.failures {
+			eprintln!("Could not restore node {}: {}", failure.node, failure.error);
+		}
+		for edge in result.skipped_edges {
+			eprintln!("Skipped invalid edge {}", edge);
+		}
+	}
+	if parsed_args.opt_present("play") || parsed_args.opt_present("load") {
+		// Render a loaded project without waiting for an RPC
+		// client, e.g. when stdin is not attached to one
+		let _ = background_engine.read().controls.send(ControlMsg::Play);
+	}
+	io.extend_with(graph_service.to_delegate());
+	let mut engine_service = EngineService::using_engine(background_engine.clone());
+	if let Some(speaker) = speaker_config {
+		engine_service = engine_service.restarting_speaker(shared_graph, speaker);
+	}
+	io.extend_with(engine_service.to_delegate());
+	
+	match rpc_transport.as_str() {
+		"stdio" => StdioServerBuilder::new(io).build(),
+		"tcp" => {
+			let address = parsed_args.opt_str("rpc-address").unwrap_or_else(|| "127.0.0.1:7878".to_owned());
+			let server = TcpServerBuilder::new(io)
+				.start(&address.parse().unwrap_or_else(|e| exit_with_usage(&format!("Invalid RPC address: {}", e), &program, &opts)))
+				.unwrap_or_else(|e| {
+					eprintln!("Could not start the TCP server: {}", e);
+					process::exit(1);
+				});
+			server.wait();
+		},
+		_ => exit_with_usage("Unrecognized RPC transport, try stdio or tcp.", &program, &opts)
+	}
+	
+	// Release the nodes held by the undo history (closing
+	// their files and commands) before waiting for the engine
+	let discarded = edit_history.lock().clear();
+	drop(discarded);
 	
-	ServerBuilder::new(io).build();
+	// Let the engine finish (e.g. render the remaining audio and
+	// finalize the output file) before its thread is killed
+	let background_engine = background_engine.read();
+	if background_engine.controls.send(ControlMsg::Finish).is_ok() {
+		background_engine.wait_until_stopped();
+	}
 }
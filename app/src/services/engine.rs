@@ -0,0 +1,167 @@
+use jsonrpc_core::Result as RpcResult;
+use jsonrpc_core::{Error as RpcError, ErrorCode as RpcErrorCode};
+use bassbox_rpc_api::{RpcHost, RpcDevice, RpcFormat, RpcEngineStats, RpcEngineStatus, RpcPlaybackState, RpcSpeakerOutput, EngineServiceRpc};
+use super::rpcutils::server_error;
+use bassbox_core::engine::{AudioEngine, BackgroundEngine, ControlMsg, PlaybackState};
+use bassbox_core::engine::speaker::{self, SpeakerEngine, HostInfo, DeviceInfo, FormatInfo};
+use bassbox_core::engine::stats::EngineStatsSnapshot;
+use bassbox_core::graph::SharedAudioGraph;
+use bassbox_core::processing::DspNode;
+use std::sync::Arc;
+use std::time::Duration;
+use parking_lot::{Mutex, RwLock};
+
+/// The running engine, which is replaced
+/// when the engine is restarted.
+pub type SharedEngine = Arc<RwLock<BackgroundEngine>>;
+
+/// How long a restart waits for the
+/// previous engine to stop.
+const STOP_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The engine service implementation that exposes
+/// the audio hardware and the running engine.
+pub struct EngineService {
+	engine: SharedEngine,
+	/// The graph and the configuration of the speaker
+	/// engine, if it is running and can be restarted
+	speaker: Option<(SharedAudioGraph<DspNode>, Mutex<SpeakerEngine>)>
+}
+
+impl EngineService {
+	pub fn using_engine(engine: SharedEngine) -> EngineService {
+		EngineService { engine: engine, speaker: None }
+	}
+	
+	/// Allows restarting the speaker engine (which has
+	/// been started using the given configuration).
+	pub fn restarting_speaker(self, shared_graph: SharedAudioGraph<DspNode>, speaker: SpeakerEngine) -> EngineService {
+		EngineService { speaker: Some((shared_graph, Mutex::new(speaker))), ..self }
+	}
+	
+	fn status_of(engine: &BackgroundEngine) -> RpcEngineStatus {
+		RpcEngineStatus {
+			state: match engine.state.get() {
+				PlaybackState::Playing => RpcPlaybackState::Playing,
+				PlaybackState::Paused => RpcPlaybackState::Paused,
+				PlaybackState::Stopped => RpcPlaybackState::Stopped
+			},
+			sample_hz: engine.sample_hz(),
+			channels: engine.channels,
+			device: engine.device.clone()
+		}
+	}
+}
+
+impl EngineServiceRpc for EngineService {
+	fn devices(&self) -> RpcResult<Vec<RpcHost>> {
+		Ok(speaker::list_devices().into_iter().map(RpcHost::from_host_info).collect())
+	}
+	
+	fn play(&self) -> RpcResult<()> {
+		self.engine.read().controls.send(ControlMsg::Play).map_err(|e| server_error(e))
+	}
+	
+	fn pause(&self) -> RpcResult<()> {
+		self.engine.read().controls.send(ControlMsg::Pause).map_err(|e| server_error(e))
+	}
+	
+	fn stop(&self) -> RpcResult<()> {
+		self.engine.read().controls.send(ControlMsg::Stop).map_err(|e| server_error(e))
+	}
+	
+	fn status(&self) -> RpcResult<RpcEngineStatus> {
+		Ok(EngineService::status_of(&self.engine.read()))
+	}
+	
+	fn restart(&self, output: RpcSpeakerOutput) -> RpcResult<RpcEngineStatus> {
+		let (shared_graph, speaker) = self.speaker.as_ref().ok_or_else(|| server_error("Only the speaker engine can be restarted"))?;
+		if output.buffer_size.is_some() {
+			return Err(RpcError {
+				code: RpcErrorCode::InvalidParams,
+				message: "The buffer size can not be chosen with the current audio backend".to_owned(),
+				data: None
+			});
+		}
+		
+		// Hold the engine until the new one is running
+		// to keep other requests from controlling it
+		let mut engine = self.engine.write();
+		let mut speaker = speaker.lock();
+		// The engine may already have been stopped
+		let _ = engine.controls.send(ControlMsg::Stop);
+		if !engine.wait_until_stopped_for(STOP_TIMEOUT) {
+			return Err(server_error("The engine did not stop in time"));
+		}
+		let renderer = shared_graph.reattach().ok_or_else(|| server_error("The engine has not released the graph"))?;
+		
+		let restarted = SpeakerEngine {
+			host: output.host,
+			device: output.device,
+			sample_hz: output.sample_hz,
+			..speaker.clone()
+		};
+		// If starting fails, the renderer is dropped and
+		// the graph is parked again for another attempt
+		*engine = restarted.clone().run_async(renderer).map_err(|e| server_error(e))?;
+		*speaker = restarted;
+		Ok(EngineService::status_of(&engine))
+	}
+	
+	fn stats(&self) -> RpcResult<RpcEngineStats> {
+		Ok(RpcEngineStats::from_snapshot(self.engine.read().stats.snapshot()))
+	}
+}
+
+trait FromSnapshotExt {
+	fn from_snapshot(stats: EngineStatsSnapshot) -> Self;
+}
+
+impl FromSnapshotExt for RpcEngineStats {
+	fn from_snapshot(stats: EngineStatsSnapshot) -> RpcEngineStats {
+		let average = |total: f64| if stats.callbacks > 0 { total / stats.callbacks as f64 } else { 0.0 };
+		RpcEngineStats {
+			callbacks: stats.callbacks,
+			late_callbacks: stats.late_callbacks,
+			stream_errors: stats.stream_errors,
+			frames: stats.frames,
+			average_callback_secs: average(stats.total_callback_secs),
+			max_callback_secs: stats.max_callback_secs,
+			average_update_secs: average(stats.total_update_secs),
+			max_update_secs: stats.max_update_secs
+		}
+	}
+}
+
+trait FromHostInfoExt {
+	fn from_host_info(host: HostInfo) -> Self;
+}
+
+impl FromHostInfoExt for RpcHost {
+	fn from_host_info(host: HostInfo) -> RpcHost {
+		RpcHost {
+			name: host.name,
+			is_default: host.is_default,
+			devices: host.devices.into_iter().map(RpcDevice::from_device_info).collect()
+		}
+	}
+}
+
+trait FromDeviceInfoExt {
+	fn from_device_info(device: DeviceInfo) -> Self;
+}
+
+impl FromDeviceInfoExt for RpcDevice {
+	fn from_device_info(device: DeviceInfo) -> RpcDevice {
+		RpcDevice {
+			name: device.name,
+			is_default: device.is_default,
+			formats: device.formats.into_iter().map(|f: FormatInfo| RpcFormat {
+				channels: f.channels,
+				min_sample_hz: f.min_sample_hz,
+				max_sample_hz: f.max_sample_hz,
+				sample_format: f.sample_format
+			}).collect()
+		}
+	}
+}
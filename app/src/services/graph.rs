@@ -1,95 +1,589 @@
 use jsonrpc_core::Result as RpcResult;
 use jsonrpc_core::{Error as RpcError, ErrorCode as RpcErrorCode};
-use dsp::EdgeRef;
-use bassbox_rpc_api::{RpcNode, RpcNodeIndex, RpcEdge, RpcEdgeIndex, RpcGraph, AudioGraphServiceRpc};
+use bassbox_rpc_api::{RpcNode, RpcNodeIndex, RpcEdge, RpcEdgeIndex, RpcEdgeSelector, RpcGraph, RpcTrackMetadata, RpcCoverArt, RpcResampleQuality, RpcGraphOp, RpcNodeRef, RpcBatchResult, RpcProject, RpcRestoreFailure, RpcLoadResult, RpcHistory, PROJECT_VERSION, AudioGraphServiceRpc};
 use super::rpcutils::server_error;
 use bassbox_core::processing::{DspNode, filter::{Disableable, CutoffFreq, IIRHighpassFilter, IIRLowpassFilter}};
-use bassbox_core::graph::{AudioGraph, SharedAudioGraph};
-use bassbox_core::source::{AudioSource, pausable::Pausable, conv::Converting, file::FileSource, command::CommandSource};
-use bassbox_core::engine::BackgroundEngine;
+use bassbox_core::graph::{AudioGraph, SharedAudioGraph, GraphTimeout, NodeId, EdgeId, GraphEdit, EditError, prepend};
+use bassbox_core::history::{EditHistory, HistoryEntry};
+use bassbox_core::source::{AudioSource, Seekable, pausable::Pausable, conv::Converting, file::{FileSource, FileSourceError}, command::CommandSource, metadata::TrackMetadata, resample::ResampleQuality};
+use super::engine::SharedEngine;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::mem;
+use std::sync::Arc;
+use parking_lot::Mutex;
+use serde_json::json;
+
+/// The number of edits that can be undone by default.
+/// Since every edit may keep nodes (and thus open files
+/// and running commands) alive, this is kept small.
+pub const DEFAULT_HISTORY_CAPACITY: usize = 20;
+
+/// The number of nodes, edges and names a graph
+/// snapshot initially has room for. If the graph
+/// does not fit, the snapshot is retaken with
+/// this much room to spare.
+const SNAPSHOT_HEADROOM: usize = 32;
 
 /// The audio graph service implementation that holds a
-/// reference to the shared audio graph and the engine's
-/// control channel.
+/// reference to the shared audio graph, the engine's
+/// control channel and the history of edits.
+///
+/// Since the history keeps removed and replaced nodes
+/// alive (along with their open files and running
+/// commands), it should be cleared on shutdown.
 pub struct AudioGraphService {
 	shared_graph: SharedAudioGraph<DspNode>,
-	engine: BackgroundEngine
+	engine: SharedEngine,
+	history: Arc<Mutex<EditHistory<DspNode>>>
+}
+
+/// An edit that failed on the render thread.
+struct Rejected {
+	error: RpcError,
+	/// What the failed edit handed back (such as nodes),
+	/// which is dropped outside of the render thread
+	garbage: Option<Box<dyn Send>>
+}
+
+impl Rejected {
+	fn with_code(code: RpcErrorCode, e: EditError<DspNode>) -> Rejected {
+		let EditError { message, edits } = e;
+		Rejected { error: RpcError { code: code, message: message, data: None }, garbage: Some(Box::new(edits)) }
+	}
+}
+
+impl From<RpcError> for Rejected {
+	fn from(error: RpcError) -> Rejected {
+		Rejected { error: error, garbage: None }
+	}
+}
+
+impl From<EditError<DspNode>> for Rejected {
+	fn from(e: EditError<DspNode>) -> Rejected {
+		let EditError { message, edits } = e;
+		Rejected { error: server_error(message), garbage: Some(Box::new(edits)) }
+	}
+}
+
+/// Reports that the engine did not apply an operation,
+/// dropping the closure (and e.g. the nodes it holds) on
+/// the calling thread.
+fn unresponsive<F>(e: GraphTimeout<F>) -> RpcError {
+	server_error(e.message)
+}
+
+/// Reports that a file could not be opened. If its format
+/// was not recognized, the probed formats are attached.
+fn unopenable(e: FileSourceError) -> RpcError {
+	let mut error = server_error(e.to_string());
+	if let FileSourceError::UnrecognizedFormat(ref unrecognized) = e {
+		error.data = Some(json!({
+			"filePath": unrecognized.file_path,
+			"probed": unrecognized.probed.iter().map(|format| format!("{:?}", format)).collect::<Vec<_>>(),
+			"extension": unrecognized.extension
+		}));
+	}
+	error
 }
 
 impl AudioGraphService {
-	pub fn using_graph(shared_graph: SharedAudioGraph<DspNode>, engine: BackgroundEngine) -> AudioGraphService {
-		AudioGraphService { shared_graph: shared_graph, engine: engine }
+	pub fn using_graph(shared_graph: SharedAudioGraph<DspNode>, engine: SharedEngine) -> AudioGraphService {
+		AudioGraphService { shared_graph: shared_graph, engine: engine, history: Arc::new(Mutex::new(EditHistory::new(DEFAULT_HISTORY_CAPACITY))) }
+	}
+	
+	/// Limits the number of edits that can be undone.
+	pub fn with_history_capacity(self, capacity: usize) -> AudioGraphService {
+		AudioGraphService { history: Arc::new(Mutex::new(EditHistory::new(capacity))), ..self }
+	}
+	
+	/// Fetches the history of edits, which remains
+	/// accessible after the service has been handed
+	/// to the RPC server (e.g. to clear it on shutdown).
+	pub fn edit_history(&self) -> Arc<Mutex<EditHistory<DspNode>>> {
+		self.history.clone()
+	}
+	
+	/// Performs an edit on the graph and records it in the
+	/// history. The closure runs on the render thread and
+	/// returns the result, a description and the inverse
+	/// of the edit.
+	fn edit<T, F>(&self, f: F) -> RpcResult<T> where T: Send + 'static, F: FnOnce(&mut AudioGraph<DspNode>) -> Result<(T, String, Vec<GraphEdit<DspNode>>), Rejected> + Send + 'static {
+		// The history is locked first to ensure that
+		// its entries are recorded in the same order
+		// as the edits are applied
+		let mut history = self.history.lock();
+		let (value, description, inverse) = self.shared_graph.with_graph(f).map_err(unresponsive)?.map_err(|rejected| {
+			drop(rejected.garbage);
+			rejected.error
+		})?;
+		let discarded = history.record(description, inverse);
+		drop(history);
+		// Removed and replaced nodes are held by the history,
+		// thus discarded entries may involve I/O when dropped
+		drop(discarded);
+		Ok(value)
+	}
+	
+	/// Reverts a history entry on the graph. If the engine
+	/// does not respond, the entry is discarded like one
+	/// that could not be reverted.
+	fn revert(&self, entry: HistoryEntry<DspNode>) -> Result<HistoryEntry<DspNode>, EditError<DspNode>> {
+		self.shared_graph.with_graph(move |graph| entry.revert(graph)).unwrap_or_else(|e| Err(EditError::new(e.message)))
+	}
+	
+	/// Copies the graph on the render thread, building
+	/// the RPC structures (which involves allocating)
+	/// on this thread.
+	fn snapshot(&self) -> RpcResult<RpcGraph> {
+		let mut size = SnapshotSize { nodes: SNAPSHOT_HEADROOM, edges: SNAPSHOT_HEADROOM, names: SNAPSHOT_HEADROOM };
+		loop {
+			let mut snapshot = GraphSnapshot::with_capacity(size);
+			let (snapshot, filled) = self.shared_graph.with_graph(move |graph| {
+				let filled = snapshot.fill(graph);
+				// The snapshot is handed back either way,
+				// to be dropped off the render thread
+				(snapshot, filled)
+			}).map_err(unresponsive)?;
+			match filled {
+				Ok(()) => return Ok(snapshot.into_rpc_graph()),
+				Err(required) => size = SnapshotSize {
+					nodes: required.nodes + SNAPSHOT_HEADROOM,
+					edges: required.edges + SNAPSHOT_HEADROOM,
+					names: required.names + SNAPSHOT_HEADROOM
+				}
+			}
+		}
+	}
+	
+	/// Applies a single edit and records it in the history.
+	fn apply_edit(&self, description: String, edit: GraphEdit<DspNode>) -> RpcResult<Option<usize>> {
+		self.edit(move |graph| {
+			let applied = graph.apply(edit)?;
+			Ok((applied.created, description, applied.inverse))
+		})
 	}
 }
 
 impl AudioGraphServiceRpc for AudioGraphService {
 	fn get(&self) -> RpcResult<RpcGraph> {
-		let graph = self.shared_graph.lock();
-		Ok(RpcGraph::from_audio_graph(&graph))
+		self.snapshot()
 	}
 	
 	fn add_node(&self, node: RpcNode) -> RpcResult<RpcNodeIndex> {
-		node.into_dsp_node(self.engine.sample_hz).map(|node| self.shared_graph.lock().add_node(node).index())
+		let node = node.into_dsp_node(self.engine.read().sample_hz())?;
+		self.edit(move |graph| {
+			let id = graph.add_node(node);
+			Ok((id, format!("Add node {}", id), vec![GraphEdit::RemoveNode { id: id }]))
+		})
 	}
 	
 	fn remove_node(&self, index: RpcNodeIndex) -> RpcResult<()> {
-		self.shared_graph.lock().remove_node(index.into());
-		Ok(())
+		// The removed node is kept in the history, so that
+		// it can be restored (e.g. along with its file's
+		// playback position) when undoing
+		self.apply_edit(format!("Remove node {}", index), GraphEdit::RemoveNode { id: index }).map(|_| ())
 	}
 	
 	fn replace_node(&self, index: RpcNodeIndex, node: RpcNode) -> RpcResult<()> {
-		node.into_dsp_node(self.engine.sample_hz).and_then(|node| {
-			let mut graph = self.shared_graph.lock();
-			let node_ref = graph.node_mut(index.into()).ok_or_else(|| server_error(format!("Node at {} does not exist", index)))?;
-			*node_ref = node;
-			Ok(())
-		})
+		let node = node.into_dsp_node(self.engine.read().sample_hz())?;
+		self.apply_edit(format!("Replace node {}", index), GraphEdit::ReplaceNode { id: index, node: node }).map(|_| ())
 	}
 	
-	fn add_edge(&self, edge: RpcEdge) -> RpcResult<RpcEdgeIndex> {
-		match self.shared_graph.lock().add_connection(edge.src.into(), edge.dest.into()) {
-			Ok(edge_index) => Ok(edge_index.index()),
-			Err(..) => Err(RpcError {
+	fn seek(&self, index: RpcNodeIndex, secs: f64) -> RpcResult<()> {
+		// Seeking may decode large parts of the file, thus the
+		// node is taken out of the graph (leaving an empty node
+		// in its place) and seeked off the render thread. Holding
+		// the history's lock keeps edits from touching the node
+		// in the meantime.
+		let _history = self.history.lock();
+		let mut node = self.shared_graph.with_graph(move |graph| match graph.node_mut(index) {
+			Some(node) => match *node {
+				DspNode::File(..) => Ok(mem::replace(node, DspNode::Empty)),
+				_ => Err(RpcError {
+					code: RpcErrorCode::InvalidParams,
+					message: format!("Node at {} is not seekable", index),
+					data: None
+				})
+			},
+			None => Err(server_error(format!("Node at {} does not exist", index)))
+		}).map_err(unresponsive)??;
+		let seeked = match node {
+			DspNode::File(ref mut source) => source.seek_secs(secs.max(0.0)),
+			_ => unreachable!("Only file nodes are taken out")
+		};
+		self.shared_graph.with_graph(move |graph| {
+			if let Some(slot) = graph.node_mut(index) {
+				*slot = node;
+			}
+		}).map_err(|_| server_error(format!("The engine did not respond in time, thus node {} has been left empty", index)))?;
+		seeked.map_err(|e| server_error(e))
+	}
+	
+	fn get_node_metadata(&self, index: RpcNodeIndex) -> RpcResult<RpcTrackMetadata> {
+		let metadata = self.shared_graph.with_graph(move |graph| match graph.node(index) {
+			Some(DspNode::File(source)) => Ok(source.wrapped.wrapped().metadata().clone()),
+			Some(..) => Err(RpcError {
 				code: RpcErrorCode::InvalidParams,
-				message: "This edge would create a cycle in the graph".to_owned(),
+				message: format!("Node at {} has no metadata", index),
 				data: None
-			})
+			}),
+			None => Err(server_error(format!("Node at {} does not exist", index)))
+		}).map_err(unresponsive)??;
+		// Encoding the cover art is comparatively
+		// expensive, thus we do so on this thread
+		Ok(RpcTrackMetadata::from_track_metadata(&metadata))
+	}
+	
+	fn add_edge(&self, edge: RpcEdge) -> RpcResult<RpcEdgeIndex> {
+		self.edit(move |graph| {
+			if !graph.contains_node(edge.src) || !graph.contains_node(edge.dest) {
+				return Err(server_error(format!("Edge {} -> {} connects nodes that do not exist", edge.src, edge.dest)).into());
+			}
+			let applied = graph.apply(GraphEdit::AddEdge { id: None, src: edge.src, dest: edge.dest })
+				.map_err(|e| Rejected::with_code(RpcErrorCode::InvalidParams, e))?;
+			let edge_id = applied.created.expect("Adding an edge yields its index");
+			Ok((edge_id, format!("Add edge {} -> {}", edge.src, edge.dest), applied.inverse))
+		})
+	}
+	
+	fn remove_edge(&self, edge: RpcEdgeSelector) -> RpcResult<()> {
+		self.edit(move |graph| {
+			let edge_id = match edge {
+				RpcEdgeSelector::Index(index) => index,
+				RpcEdgeSelector::Between(RpcEdge { src, dest }) => graph.find_edge(src, dest)
+					.ok_or_else(|| server_error(format!("There is no edge from {} to {}", src, dest)))?
+			};
+			let applied = graph.apply(GraphEdit::RemoveEdge { id: edge_id })?;
+			Ok(((), format!("Remove edge {}", edge_id), applied.inverse))
+		})
+	}
+	
+	fn apply_batch(&self, ops: Vec<RpcGraphOp>) -> RpcResult<RpcBatchResult> {
+		// Constructing nodes may involve I/O (e.g. opening
+		// files or spawning processes), thus we do so for
+		// all nodes of the batch off the render thread
+		let sample_hz = self.engine.read().sample_hz();
+		let ops = ops.into_iter()
+			.map(|op| BatchOp::from_rpc_op(op, sample_hz))
+			.collect::<RpcResult<Vec<_>>>()?;
+		let description = format!("Apply a batch of {} operations", ops.len());
+		
+		self.edit(move |graph| {
+			let mut result = RpcBatchResult::default();
+			let mut inverse = Vec::new();
+			let mut ops = ops.into_iter().enumerate();
+			
+			while let Some((i, op)) = ops.next() {
+				let applied = op.into_edit(&result.placeholders)
+					.map_err(|(message, op)| (EditError::new(message), Some(op)))
+					.and_then(|(edit, created)| match graph.apply(edit) {
+						Ok(applied) => Ok((applied, created)),
+						Err(e) => Err((e, None))
+					});
+				match applied {
+					Ok((applied, created)) => {
+						match (created, applied.created) {
+							(Created::Node(Some(placeholder)), Some(id)) => { result.placeholders.insert(placeholder, id); },
+							(Created::Edge, Some(id)) => result.edges.push(id),
+							_ => {}
+						}
+						prepend(&mut inverse, applied.inverse);
+					},
+					Err((e, failed_op)) => {
+						let EditError { message, mut edits } = e;
+						edits.extend(graph.revert(inverse));
+						let remaining: Vec<BatchOp> = failed_op.into_iter().chain(ops.map(|(_, op)| op)).collect();
+						return Err(Rejected {
+							error: RpcError {
+								code: RpcErrorCode::InvalidParams,
+								message: format!("Operation {} failed, thus the batch was not applied: {}", i, message),
+								data: None
+							},
+							garbage: Some(Box::new((edits, remaining)))
+						});
+					}
+				}
+			}
+			
+			Ok((result, description, inverse))
+		})
+	}
+	
+	fn set_node_name(&self, index: RpcNodeIndex, name: Option<String>) -> RpcResult<()> {
+		let description = match name {
+			Some(ref name) => format!("Name node {} '{}'", index, name),
+			None => format!("Unname node {}", index)
+		};
+		self.apply_edit(description, GraphEdit::SetName { id: index, name: name.map(|name| name.into()) }).map(|_| ())
+	}
+	
+	fn save(&self, path: String) -> RpcResult<()> {
+		let project = RpcProject {
+			version: PROJECT_VERSION,
+			graph: self.snapshot()?
+		};
+		let file = File::create(&path).map_err(|e| server_error(format!("Could not create {}: {}", path, e)))?;
+		serde_json::to_writer_pretty(BufWriter::new(file), &project).map_err(|e| server_error(format!("Could not write {}: {}", path, e)))
+	}
+	
+	fn load(&self, path: String) -> RpcResult<RpcLoadResult> {
+		let file = File::open(&path).map_err(|e| server_error(format!("Could not open {}: {}", path, e)))?;
+		let project: RpcProject = serde_json::from_reader(BufReader::new(file)).map_err(|e| RpcError {
+			code: RpcErrorCode::InvalidParams,
+			message: format!("{} is not a valid project file: {}", path, e),
+			data: None
+		})?;
+		if project.version > PROJECT_VERSION {
+			return Err(server_error(format!("{} uses a newer project format (version {})", path, project.version)));
 		}
+		let RpcGraph { nodes, edges, names, master } = project.graph;
+		let mut result = RpcLoadResult::default();
+		
+		// Restore the nodes (in their original order) off
+		// the render thread, since this involves opening
+		// files and spawning processes
+		let mut nodes: Vec<_> = nodes.into_iter().collect();
+		nodes.sort_by_key(|&(index, _)| index);
+		let sample_hz = self.engine.read().sample_hz();
+		let restored: Vec<_> = nodes.into_iter().map(|(index, node)| {
+			let node = node.into_dsp_node(sample_hz).unwrap_or_else(|e| {
+				result.failures.push(RpcRestoreFailure { node: index, error: e.message });
+				DspNode::Empty
+			});
+			(index, node)
+		}).collect();
+		let mut edges: Vec<_> = edges.into_iter().collect();
+		edges.sort_by_key(|&(index, _)| index);
+		let names: Vec<(RpcNodeIndex, Arc<str>)> = names.into_iter().map(|(index, name)| (index, name.into())).collect();
+		
+		let mut history = self.history.lock();
+		let (result, removed) = self.shared_graph.with_graph(move |graph| {
+			let ids: Vec<NodeId> = graph.nodes().map(|(id, _)| id).collect();
+			let removed: Vec<DspNode> = ids.into_iter().filter_map(|id| graph.remove_node(id)).collect();
+			
+			for (index, node) in restored {
+				result.nodes.insert(index, graph.add_node(node));
+			}
+			for (index, name) in names {
+				if let Some(&id) = result.nodes.get(&index) {
+					graph.set_name(id, Some(name));
+				}
+			}
+			for (index, edge) in edges {
+				let added = match (result.nodes.get(&edge.src), result.nodes.get(&edge.dest)) {
+					(Some(&src), Some(&dest)) => graph.add_edge(src, dest).is_ok(),
+					_ => false
+				};
+				if !added {
+					result.skipped_edges.push(index);
+				}
+			}
+			graph.set_master(master.and_then(|index| result.nodes.get(&index).cloned()));
+			(result, removed)
+		}).map_err(unresponsive)?;
+		
+		// The history refers to the replaced nodes,
+		// thus it is discarded
+		let discarded = history.clear();
+		drop(history);
+		drop(removed);
+		drop(discarded);
+		Ok(result)
 	}
+	
+	fn undo(&self) -> RpcResult<String> {
+		let mut history = self.history.lock();
+		history.undo(|entry| self.revert(entry)).map_err(|e| server_error(e))
+	}
+	
+	fn redo(&self) -> RpcResult<String> {
+		let mut history = self.history.lock();
+		history.redo(|entry| self.revert(entry)).map_err(|e| server_error(e))
+	}
+	
+	fn history(&self) -> RpcResult<RpcHistory> {
+		let history = self.history.lock();
+		Ok(RpcHistory {
+			undo: history.undoable().map(|description| description.to_owned()).collect(),
+			redo: history.redoable().map(|description| description.to_owned()).collect()
+		})
+	}
+	
+	fn clear_history(&self) -> RpcResult<()> {
+		let discarded = self.history.lock().clear();
+		// Dropping the nodes may involve I/O,
+		// thus we do so outside of the lock
+		drop(discarded);
+		Ok(())
+	}
+}
+
+/// What a batch operation adds to the graph.
+enum Created {
+	/// A node with an optional placeholder
+	Node(Option<String>),
+	Edge,
+	Nothing
 }
 
-trait FromDspNodeExt {
-	fn from_dsp_node(node: &DspNode) -> Self;
+/// A batch operation whose nodes have
+/// already been constructed.
+enum BatchOp {
+	AddNode { node: DspNode, placeholder: Option<String> },
+	RemoveNode { node: RpcNodeRef },
+	ReplaceNode { node: RpcNodeRef, replacement: DspNode },
+	AddEdge { src: RpcNodeRef, dest: RpcNodeRef },
+	RemoveEdge { edge: RpcEdgeIndex },
+	SetMaster { node: RpcNodeRef }
 }
 
-impl FromDspNodeExt for RpcNode {
-	fn from_dsp_node(node: &DspNode) -> RpcNode {
+impl BatchOp {
+	fn from_rpc_op(op: RpcGraphOp, target_sample_hz: f64) -> RpcResult<BatchOp> {
+		Ok(match op {
+			RpcGraphOp::AddNode { node, placeholder } => BatchOp::AddNode { node: node.into_dsp_node(target_sample_hz)?, placeholder: placeholder },
+			RpcGraphOp::RemoveNode { node } => BatchOp::RemoveNode { node: node },
+			RpcGraphOp::ReplaceNode { node, replacement } => BatchOp::ReplaceNode { node: node, replacement: replacement.into_dsp_node(target_sample_hz)? },
+			RpcGraphOp::AddEdge { src, dest } => BatchOp::AddEdge { src: src, dest: dest },
+			RpcGraphOp::RemoveEdge { edge } => BatchOp::RemoveEdge { edge: edge },
+			RpcGraphOp::SetMaster { node } => BatchOp::SetMaster { node: node }
+		})
+	}
+	
+	/// Converts the operation into a graph edit, resolving
+	/// placeholders. On failure, the operation is handed back
+	/// since it may hold a node.
+	fn into_edit(self, placeholders: &HashMap<String, NodeId>) -> Result<(GraphEdit<DspNode>, Created), (String, BatchOp)> {
+		let resolve = |node: &RpcNodeRef| match *node {
+			RpcNodeRef::Index(id) => Ok(id),
+			RpcNodeRef::Placeholder(ref name) => placeholders.get(name).cloned().ok_or_else(|| format!("Unknown placeholder '{}'", name))
+		};
+		// Resolve the referenced nodes (if any) before
+		// consuming the operation
+		let resolved = match self {
+			BatchOp::AddNode { placeholder: Some(ref name), .. } if placeholders.contains_key(name) => Err(format!("Placeholder '{}' is used more than once", name)),
+			BatchOp::AddNode { .. } | BatchOp::RemoveEdge { .. } => Ok((0, 0)),
+			BatchOp::RemoveNode { ref node } | BatchOp::ReplaceNode { ref node, .. } | BatchOp::SetMaster { ref node } => resolve(node).map(|id| (id, 0)),
+			BatchOp::AddEdge { ref src, ref dest } => resolve(src).and_then(|src| resolve(dest).map(|dest| (src, dest)))
+		};
+		let (id, dest) = match resolved {
+			Ok(ids) => ids,
+			Err(e) => return Err((e, self))
+		};
+		Ok(match self {
+			BatchOp::AddNode { node, placeholder } => (GraphEdit::AddNode { id: None, node: node }, Created::Node(placeholder)),
+			BatchOp::RemoveNode { .. } => (GraphEdit::RemoveNode { id: id }, Created::Nothing),
+			BatchOp::ReplaceNode { replacement, .. } => (GraphEdit::ReplaceNode { id: id, node: replacement }, Created::Nothing),
+			BatchOp::AddEdge { .. } => (GraphEdit::AddEdge { id: None, src: id, dest: dest }, Created::Edge),
+			BatchOp::RemoveEdge { edge } => (GraphEdit::RemoveEdge { id: edge }, Created::Nothing),
+			BatchOp::SetMaster { .. } => (GraphEdit::SetMaster { id: Some(id) }, Created::Nothing)
+		})
+	}
+}
+
+/// A copy of a node's description that can be taken
+/// without allocating, since it only shares its strings.
+enum NodeSnapshot {
+	/// A node whose description holds no strings
+	Plain(RpcNode),
+	File {
+		file_path: Arc<str>,
+		paused: bool,
+		position_secs: f64,
+		duration_secs: Option<f64>,
+		error: Option<Arc<str>>,
+		resample_quality: RpcResampleQuality
+	},
+	Command {
+		command: Arc<str>,
+		args: Arc<[String]>,
+		sample_hz: f64,
+		takes_input: bool,
+		paused: bool,
+		resample_quality: RpcResampleQuality
+	}
+}
+
+impl NodeSnapshot {
+	fn of(node: &DspNode) -> NodeSnapshot {
 		match *node {
-			DspNode::Empty => RpcNode::Empty,
-			DspNode::Silence => RpcNode::Silence,
+			DspNode::Empty => NodeSnapshot::Plain(RpcNode::Empty),
+			DspNode::Silence => NodeSnapshot::Plain(RpcNode::Silence),
 			DspNode::File(Pausable { wrapped: ref converting, paused }) => {
 				let source = converting.wrapped();
-				RpcNode::File {
-					file_path: source.file_path().to_owned(),
-					paused: paused
+				NodeSnapshot::File {
+					file_path: source.file_path().clone(),
+					paused: paused,
+					position_secs: (source.position_secs() - converting.latency_secs()).max(0.0),
+					duration_secs: source.duration_secs(),
+					error: source.error().cloned(),
+					resample_quality: RpcResampleQuality::from_quality(converting.quality())
 				}
 			},
 			DspNode::Command(Pausable { wrapped: ref converting, paused }) => {
 				let source = converting.wrapped();
-				RpcNode::Command {
-					command: source.command().to_owned(),
-					args: source.args().iter().map(|s| s.to_owned()).collect(),
+				NodeSnapshot::Command {
+					command: source.command().clone(),
+					args: source.args().clone(),
 					sample_hz: source.sample_hz(),
 					takes_input: source.input().is_some(),
-					paused: paused
+					paused: paused,
+					resample_quality: RpcResampleQuality::from_quality(converting.quality())
 				}
 			},
-			DspNode::DynSource(..) => RpcNode::DynSource,
-			DspNode::Volume(volume) => RpcNode::Volume { level: volume },
-			DspNode::IIRLowpass(Disableable { wrapped: ref filter, disabled }) => RpcNode::IIRLowpass { cutoff_hz: filter.cutoff_hz(), disabled: disabled },
-			DspNode::IIRHighpass(Disableable { wrapped: ref filter, disabled }) => RpcNode::IIRHighpass { cutoff_hz: filter.cutoff_hz(), disabled: disabled },
-			DspNode::DynFilter(..) => RpcNode::DynFilter,
-			_ => RpcNode::Other
+			DspNode::DynSource(..) => NodeSnapshot::Plain(RpcNode::DynSource),
+			DspNode::Volume(volume) => NodeSnapshot::Plain(RpcNode::Volume { level: volume }),
+			DspNode::IIRLowpass(Disableable { wrapped: ref filter, disabled }) => NodeSnapshot::Plain(RpcNode::IIRLowpass { cutoff_hz: filter.cutoff_hz(), disabled: disabled }),
+			DspNode::IIRHighpass(Disableable { wrapped: ref filter, disabled }) => NodeSnapshot::Plain(RpcNode::IIRHighpass { cutoff_hz: filter.cutoff_hz(), disabled: disabled }),
+			DspNode::DynFilter(..) => NodeSnapshot::Plain(RpcNode::DynFilter),
+			_ => NodeSnapshot::Plain(RpcNode::Other)
+		}
+	}
+	
+	fn into_rpc_node(self) -> RpcNode {
+		match self {
+			NodeSnapshot::Plain(node) => node,
+			NodeSnapshot::File { file_path, paused, position_secs, duration_secs, error, resample_quality } => RpcNode::File {
+				file_path: file_path.to_string(),
+				paused: paused,
+				position_secs: position_secs,
+				duration_secs: duration_secs,
+				error: error.map(|e| e.to_string()),
+				resample_quality: resample_quality
+			},
+			NodeSnapshot::Command { command, args, sample_hz, takes_input, paused, resample_quality } => RpcNode::Command {
+				command: command.to_string(),
+				args: args.to_vec(),
+				sample_hz: sample_hz,
+				takes_input: takes_input,
+				paused: paused,
+				resample_quality: resample_quality
+			}
+		}
+	}
+}
+
+trait ResampleQualityExt {
+	fn from_quality(quality: ResampleQuality) -> Self;
+	
+	fn into_quality(self) -> ResampleQuality;
+}
+
+impl ResampleQualityExt for RpcResampleQuality {
+	fn from_quality(quality: ResampleQuality) -> RpcResampleQuality {
+		match quality {
+			ResampleQuality::Linear => RpcResampleQuality::Linear,
+			ResampleQuality::Cubic => RpcResampleQuality::Cubic,
+			ResampleQuality::Sinc { taps } => RpcResampleQuality::Sinc { taps: taps }
+		}
+	}
+	
+	fn into_quality(self) -> ResampleQuality {
+		match self {
+			RpcResampleQuality::Linear => ResampleQuality::Linear,
+			RpcResampleQuality::Cubic => ResampleQuality::Cubic,
+			RpcResampleQuality::Sinc { taps } => ResampleQuality::Sinc { taps: taps }
 		}
 	}
 }
@@ -104,19 +598,18 @@ impl IntoDspNodeExt for RpcNode {
 			RpcNode::Empty => Ok(DspNode::Empty),
 			RpcNode::Silence => Ok(DspNode::Silence),
 			RpcNode::Volume { level } => Ok(DspNode::Volume(level)),
-			RpcNode::File { ref file_path, paused } => Ok(DspNode::File(
-				Pausable::new(
-					Converting::to_sample_hz(
-						target_sample_hz,
-						FileSource::new(file_path.as_ref()).map_err(|e| server_error(e))?
-					),
-					paused
-				)
-			)),
-			RpcNode::Command { ref command, ref args, sample_hz, takes_input, paused } => Ok(DspNode::Command(
+			RpcNode::File { ref file_path, paused, position_secs, resample_quality, .. } => {
+				let mut source = FileSource::new(file_path.as_ref()).map_err(unopenable)?;
+				if position_secs > 0.0 {
+					source.seek_secs(position_secs).map_err(|e| server_error(e))?;
+				}
+				Ok(DspNode::File(Pausable::new(Converting::with_quality(target_sample_hz, resample_quality.into_quality(), source), paused)))
+			},
+			RpcNode::Command { ref command, ref args, sample_hz, takes_input, paused, resample_quality } => Ok(DspNode::Command(
 				Pausable::new(
-					Converting::to_sample_hz(
+					Converting::with_quality(
 						target_sample_hz,
+						resample_quality.into_quality(),
 						CommandSource::new(command, &args.iter().map(|s| s.as_ref()).collect::<Vec<_>>(), sample_hz, takes_input).map_err(|e| server_error(e))?
 					),
 					paused
@@ -138,16 +631,79 @@ impl IntoDspNodeExt for RpcNode {
 	}
 }
 
-trait FromAudioGraphExt {
-	fn from_audio_graph(graph: &AudioGraph<DspNode>) -> Self;
+trait FromTrackMetadataExt {
+	fn from_track_metadata(metadata: &TrackMetadata) -> Self;
+}
+
+impl FromTrackMetadataExt for RpcTrackMetadata {
+	fn from_track_metadata(metadata: &TrackMetadata) -> RpcTrackMetadata {
+		RpcTrackMetadata {
+			title: metadata.title.clone(),
+			artist: metadata.artist.clone(),
+			album: metadata.album.clone(),
+			album_artist: metadata.album_artist.clone(),
+			track_number: metadata.track_number.clone(),
+			date: metadata.date.clone(),
+			genre: metadata.genre.clone(),
+			cover_art: metadata.cover_art.as_ref().map(|cover_art| RpcCoverArt {
+				mime_type: cover_art.mime_type.clone(),
+				description: cover_art.description.clone(),
+				data: base64::encode(&cover_art.data)
+			})
+		}
+	}
+}
+
+/// The number of nodes, edges and names
+/// a snapshot has room for.
+#[derive(Debug, Copy, Clone)]
+struct SnapshotSize {
+	nodes: usize,
+	edges: usize,
+	names: usize
+}
+
+/// A copy of the graph that is taken on the render
+/// thread, from which the RPC structures are built
+/// afterwards. Since its vectors are allocated
+/// beforehand, taking it does not allocate.
+struct GraphSnapshot {
+	nodes: Vec<(NodeId, NodeSnapshot)>,
+	edges: Vec<(EdgeId, NodeId, NodeId)>,
+	names: Vec<(NodeId, Arc<str>)>,
+	master: Option<NodeId>
 }
 
-impl FromAudioGraphExt for RpcGraph {
-	fn from_audio_graph(graph: &AudioGraph<DspNode>) -> RpcGraph {
+impl GraphSnapshot {
+	fn with_capacity(size: SnapshotSize) -> GraphSnapshot {
+		GraphSnapshot {
+			nodes: Vec::with_capacity(size.nodes),
+			edges: Vec::with_capacity(size.edges),
+			names: Vec::with_capacity(size.names),
+			master: None
+		}
+	}
+	
+	/// Copies the graph into the snapshot. Fails with
+	/// the required size if the graph does not fit.
+	fn fill(&mut self, graph: &AudioGraph<DspNode>) -> Result<(), SnapshotSize> {
+		let size = SnapshotSize { nodes: graph.nodes().count(), edges: graph.edges().count(), names: graph.names().count() };
+		if size.nodes > self.nodes.capacity() || size.edges > self.edges.capacity() || size.names > self.names.capacity() {
+			return Err(size);
+		}
+		self.nodes.extend(graph.nodes().map(|(id, node)| (id, NodeSnapshot::of(node))));
+		self.edges.extend(graph.edges());
+		self.names.extend(graph.names().map(|(id, name)| (id, name.clone())));
+		self.master = graph.master();
+		Ok(())
+	}
+	
+	fn into_rpc_graph(self) -> RpcGraph {
 		RpcGraph {
-			nodes: graph.node_references().map(|(id, node)| (id.index(), RpcNode::from_dsp_node(node))).collect(),
-			edges: graph.edge_references().map(|edge| RpcEdge::between(edge.source().index(), edge.target().index())).collect(),
-			master: graph.master_index().map(|i| i.index())
+			nodes: self.nodes.into_iter().map(|(id, node)| (id, node.into_rpc_node())).collect(),
+			edges: self.edges.into_iter().map(|(id, src, dest)| (id, RpcEdge::between(src, dest))).collect(),
+			names: self.names.into_iter().map(|(id, name)| (id, name.to_string())).collect(),
+			master: self.master
 		}
 	}
 }
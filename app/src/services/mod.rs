@@ -1,3 +1,4 @@
 //! The JSON-RPC interface exposed to the user
 pub mod graph;
+pub mod engine;
 mod rpcutils;
@@ -0,0 +1,45 @@
+//! Dithering applied before quantizing
+//! the output to a lower bit depth.
+
+use crate::audioformat::{StandardFrame, StandardSample};
+
+/// Triangular probability density function (TPDF)
+/// dither, which decorrelates the quantization
+/// error from the signal.
+///
+/// Uses a small xorshift generator, since the
+/// dither runs on the audio thread and neither
+/// needs nor may afford a cryptographic RNG.
+#[derive(Debug, Clone)]
+pub struct TpdfDither {
+	state: u32,
+	/// The size of a quantization step
+	lsb: StandardSample
+}
+
+impl TpdfDither {
+	/// Creates a dither for quantizing to
+	/// the given number of (integer) bits.
+	pub fn new(bits: u32) -> TpdfDither {
+		TpdfDither { state: 0x9E37_79B9, lsb: 1.0 / (1u32 << (bits - 1)) as StandardSample }
+	}
+
+	/// A uniformly distributed value in [0, 1).
+	fn next_uniform(&mut self) -> StandardSample {
+		self.state ^= self.state << 13;
+		self.state ^= self.state >> 17;
+		self.state ^= self.state << 5;
+		(self.state >> 8) as StandardSample / (1u32 << 24) as StandardSample
+	}
+
+	/// Adds dither noise (in the range of ±1 LSB)
+	/// to every sample of the frames.
+	pub fn apply(&mut self, frames: &mut [StandardFrame]) {
+		for frame in frames {
+			for sample in frame.iter_mut() {
+				let noise = self.next_uniform() - self.next_uniform();
+				*sample += noise * self.lsb;
+			}
+		}
+	}
+}
@@ -0,0 +1,174 @@
+use super::{AudioEngine, BackgroundEngine, ControlMsg, EngineControls, PlaybackState, SharedPlaybackState, SharedSampleRate};
+use super::stats::EngineStats;
+use crate::audioformat::{StandardFrame, StandardSample, STANDARD_CHANNELS};
+use crate::graph::GraphRenderer;
+use crate::util::exhaustible::Exhaustible;
+use crate::util::retargetable::Retargetable;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+use std::sync::{Arc, mpsc};
+use std::thread;
+use std::time::Duration;
+use log::{info, error};
+use dsp::{Frame, Node};
+use hound::{WavWriter, WavSpec, SampleFormat};
+
+/// The number of frames rendered between
+/// applying graph modifications.
+const CHUNK_FRAMES: usize = 1024;
+/// The interval at which the engine polls
+/// the graph for sources while idling.
+const IDLE_INTERVAL: Duration = Duration::from_millis(20);
+
+/// An engine that renders the graph into a WAV
+/// file as fast as possible (i.e. not in real time).
+///
+/// The engine starts paused, so that the graph can be
+/// set up before `Play`. It only considers the sources
+/// that feed into the master node: It idles while none of
+/// them is playing and stops once all of them are exhausted,
+/// the maximum duration has been reached or (after `Finish`)
+/// none of them is playing anymore.
+pub struct FileRenderEngine {
+	output_path: PathBuf,
+	sample_hz: f64,
+	bits_per_sample: u16,
+	max_duration_secs: Option<f64>
+}
+
+impl FileRenderEngine {
+	/// Creates a new render engine. Supported bit depths
+	/// are 16 and 24 (integer) as well as 32 (float).
+	pub fn new(output_path: PathBuf, sample_hz: f64, bits_per_sample: u16, max_duration_secs: Option<f64>) -> Result<FileRenderEngine, String> {
+		match bits_per_sample {
+			16 | 24 | 32 => {},
+			_ => return Err(format!("Unsupported bit depth {}, try 16, 24 or 32", bits_per_sample))
+		}
+		if !(sample_hz >= 1.0 && sample_hz <= u32::max_value() as f64) {
+			return Err(format!("Invalid sample rate {}", sample_hz));
+		}
+		Ok(FileRenderEngine {
+			output_path: output_path,
+			sample_hz: sample_hz,
+			bits_per_sample: bits_per_sample,
+			max_duration_secs: max_duration_secs
+		})
+	}
+
+	fn spec(&self) -> WavSpec {
+		WavSpec {
+			channels: STANDARD_CHANNELS as u16,
+			sample_rate: self.sample_hz as u32,
+			bits_per_sample: self.bits_per_sample,
+			sample_format: if self.bits_per_sample == 32 { SampleFormat::Float } else { SampleFormat::Int }
+		}
+	}
+}
+
+impl AudioEngine for FileRenderEngine {
+	fn run_async<N>(self, mut renderer: GraphRenderer<N>) -> Result<BackgroundEngine, String> where N: Node<StandardFrame> + Exhaustible + Retargetable + Send + 'static {
+		let sample_hz = self.sample_hz;
+		let bits_per_sample = self.bits_per_sample;
+		let max_frames = self.max_duration_secs.map(|secs| (secs * sample_hz) as u64);
+		let output_name = self.output_path.display().to_string();
+		let mut writer = WavWriter::create(&self.output_path, self.spec()).map_err(|e| format!("Could not create {}: {}", output_name, e))?;
+		let (control_sender, control_receiver) = mpsc::sync_channel(5);
+		let state = Arc::new(SharedPlaybackState::new(PlaybackState::Paused));
+		let thread_state = state.clone();
+
+		thread::spawn(move || {
+			let mut paused = true;
+			let mut finishing = false;
+			let mut rendered: u64 = 0;
+			let mut buffer = vec![StandardFrame::equilibrium(); CHUNK_FRAMES];
+
+			info!("Rendering to {}", self.output_path.display());
+
+			loop {
+				// Possibly receive a control operation message
+				if let Ok(msg) = control_receiver.try_recv() {
+					match msg {
+						ControlMsg::Play => {
+							paused = false;
+							thread_state.set(PlaybackState::Playing);
+						},
+						ControlMsg::Pause => {
+							paused = true;
+							thread_state.set(PlaybackState::Paused);
+						},
+						ControlMsg::Stop => break,
+						ControlMsg::Finish => finishing = true
+					}
+				}
+
+				let remaining = max_frames.map(|max| max - rendered).unwrap_or(CHUNK_FRAMES as u64);
+				if remaining == 0 {
+					break;
+				}
+
+				let chunk = &mut buffer[..remaining.min(CHUNK_FRAMES as u64) as usize];
+				renderer.update();
+				let graph = renderer.graph();
+				let sources: Vec<&N> = graph.master()
+					.map(|master| graph.upstream_of(master))
+					.unwrap_or_default()
+					.into_iter()
+					.filter_map(|id| graph.node(id))
+					.filter(|node| node.is_source())
+					.collect();
+				let exhausted = !sources.is_empty() && sources.iter().all(|node| node.is_exhausted());
+				let playing = sources.iter().any(|node| !node.is_exhausted() && !node.is_paused());
+
+				if exhausted || (finishing && (paused || !playing)) {
+					break;
+				} else if playing && !paused {
+					renderer.render(chunk, sample_hz);
+				} else {
+					// Wait for a source to be connected or resumed
+					// (or for the engine to be resumed) instead of
+					// rendering silence
+					thread::sleep(IDLE_INTERVAL);
+					continue;
+				}
+
+				if let Err(e) = write_chunk(&mut writer, chunk, bits_per_sample) {
+					error!("Could not write rendered audio: {}", e);
+					break;
+				}
+				rendered += chunk.len() as u64;
+			}
+
+			match writer.finalize() {
+				Ok(()) => info!("Rendered {:.2} seconds to {}", rendered as f64 / sample_hz, self.output_path.display()),
+				Err(e) => error!("Could not finalize output file: {}", e)
+			}
+			thread_state.set(PlaybackState::Stopped);
+		});
+
+		Ok(BackgroundEngine {
+			sample_rate: Arc::new(SharedSampleRate::new(sample_hz)),
+			channels: STANDARD_CHANNELS,
+			device: Some(output_name),
+			controls: EngineControls::new(control_sender),
+			state: state,
+			stats: Arc::new(EngineStats::default())
+		})
+	}
+}
+
+/// Writes the frames using the given bit depth,
+/// clipping samples outside of [-1.0, 1.0].
+fn write_chunk(writer: &mut WavWriter<BufWriter<File>>, chunk: &[StandardFrame], bits_per_sample: u16) -> hound::Result<()> {
+	for frame in chunk {
+		for &sample in frame.iter() {
+			let sample: StandardSample = sample.max(-1.0).min(1.0);
+			match bits_per_sample {
+				16 => writer.write_sample((sample * i16::max_value() as StandardSample) as i16)?,
+				24 => writer.write_sample((sample * 8_388_607.0) as i32)?,
+				_ => writer.write_sample(sample)?
+			}
+		}
+	}
+	Ok(())
+}
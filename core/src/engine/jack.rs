@@ -0,0 +1,174 @@
+use super::{AudioEngine, BackgroundEngine, ControlMsg, EngineControls, PlaybackState, SharedPlaybackState, SharedSampleRate};
+use super::stats::EngineStats;
+use crate::audioformat::{StandardFrame, STANDARD_CHANNELS};
+use crate::graph::GraphRenderer;
+use crate::util::exhaustible::Exhaustible;
+use crate::util::retargetable::Retargetable;
+use std::sync::{Arc, mpsc};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use log::{info, warn, error};
+use dsp::{Frame, Node};
+use ::jack::{Client, ClientOptions, AudioOut, ClosureProcessHandler, NotificationHandler, Control, Frames, ProcessScope, PortFlags};
+
+/// The names of the registered output ports.
+const PORT_NAMES: [&str; STANDARD_CHANNELS] = ["out_left", "out_right"];
+
+/// An engine that registers as a JACK client
+/// with a pair of stereo output ports. The sample
+/// rate and buffer size are dictated by the JACK server.
+#[derive(Debug, Clone)]
+pub struct JackEngine {
+	/// The name of the JACK client
+	pub client_name: String,
+	/// Whether the output ports are connected
+	/// to the system's playback ports
+	pub auto_connect: bool
+}
+
+impl Default for JackEngine {
+	fn default() -> JackEngine {
+		JackEngine { client_name: "bassbox".to_owned(), auto_connect: false }
+	}
+}
+
+/// Receives notifications from the JACK server
+/// on a thread of its own.
+struct Notifications {
+	sample_rate: Arc<SharedSampleRate>
+}
+
+impl NotificationHandler for Notifications {
+	fn sample_rate(&mut self, _: &Client, srate: Frames) -> Control {
+		info!("The JACK server switched to {} Hz", srate);
+		// The process callback picks up the new rate
+		self.sample_rate.set(srate as f64);
+		Control::Continue
+	}
+}
+
+impl AudioEngine for JackEngine {
+	fn run_async<N>(self, mut renderer: GraphRenderer<N>) -> Result<BackgroundEngine, String> where N: Node<StandardFrame> + Exhaustible + Retargetable + Send + 'static {
+		let (client, _status) = Client::new(&self.client_name, ClientOptions::NO_START_SERVER).map_err(|e| format!("Could not connect to the JACK server: {}", e))?;
+		let mut sample_hz = client.sample_rate() as f64;
+		let mut left_port = client.register_port(PORT_NAMES[0], AudioOut::default()).map_err(|e| format!("Could not register JACK port {}: {}", PORT_NAMES[0], e))?;
+		let mut right_port = client.register_port(PORT_NAMES[1], AudioOut::default()).map_err(|e| format!("Could not register JACK port {}: {}", PORT_NAMES[1], e))?;
+		let client_name = client.name().to_owned();
+		info!("Running as JACK client '{}' at {} Hz with {} frames per buffer", client_name, sample_hz, client.buffer_size());
+
+		let sample_rate = Arc::new(SharedSampleRate::new(sample_hz));
+		let process_sample_rate = sample_rate.clone();
+		let notifications = Notifications { sample_rate: sample_rate.clone() };
+
+		let (control_sender, control_receiver) = mpsc::sync_channel(5);
+		let stats = Arc::new(EngineStats::default());
+		let process_stats = stats.clone();
+		let state = Arc::new(SharedPlaybackState::new(PlaybackState::Playing));
+		let thread_state = state.clone();
+		let paused = Arc::new(AtomicBool::new(false));
+		let process_paused = paused.clone();
+
+		// Preallocate the buffer used to deinterleave the
+		// output. If the server switches to larger buffers,
+		// they are rendered in chunks.
+		let mut scratch = vec![StandardFrame::equilibrium(); (client.buffer_size() as usize).max(1)];
+
+		let process = ClosureProcessHandler::new(move |_: &Client, ps: &ProcessScope| -> Control {
+			let callback_start = Instant::now();
+			let frame_count = ps.n_frames() as usize;
+			let paused = process_paused.load(Ordering::Relaxed);
+
+			let current_hz = process_sample_rate.get();
+			if current_hz != sample_hz {
+				// Rate changes are rare enough to retarget (and
+				// possibly reallocate) the nodes in the callback
+				renderer.retarget(current_hz);
+				sample_hz = current_hz;
+			}
+
+			// Apply the graph modifications, even while paused
+			let update_start = Instant::now();
+			renderer.update();
+			let update_time = update_start.elapsed();
+
+			let left = left_port.as_mut_slice(ps);
+			let right = right_port.as_mut_slice(ps);
+			let mut start = 0;
+			while start < frame_count {
+				let end = (start + scratch.len()).min(frame_count);
+				let audio = &mut scratch[..end - start];
+				if paused {
+					for frame in audio.iter_mut() {
+						*frame = StandardFrame::equilibrium();
+					}
+				} else {
+					renderer.render(audio, sample_hz);
+				}
+				for (i, frame) in audio.iter().enumerate() {
+					left[start + i] = frame[0];
+					right[start + i] = frame[1];
+				}
+				start = end;
+			}
+
+			if !paused {
+				let period = Duration::from_secs_f64(frame_count as f64 / sample_hz);
+				process_stats.record_callback(frame_count, callback_start.elapsed(), update_time, period);
+			}
+			Control::Continue
+		});
+
+		let active_client = client.activate_async(notifications, process).map_err(|e| format!("Could not activate JACK client: {}", e))?;
+
+		if self.auto_connect {
+			let playback_ports = active_client.as_client().ports(None, Some("audio"), PortFlags::IS_INPUT | PortFlags::IS_PHYSICAL);
+			if playback_ports.len() < STANDARD_CHANNELS {
+				warn!("Found only {} physical playback ports, not connecting automatically", playback_ports.len());
+			} else {
+				for (port_name, playback_port) in PORT_NAMES.iter().zip(playback_ports.iter()) {
+					let source = format!("{}:{}", client_name, port_name);
+					if let Err(e) = active_client.as_client().connect_ports_by_name(&source, playback_port) {
+						error!("Could not connect {} to {}: {}", source, playback_port, e);
+					}
+				}
+			}
+		}
+
+		// The client is kept alive (and eventually deactivated)
+		// by a thread that forwards control messages, since the
+		// process callback may not block
+		thread::spawn(move || {
+			for msg in control_receiver.iter() {
+				match msg {
+					ControlMsg::Play => {
+						paused.store(false, Ordering::Relaxed);
+						thread_state.set(PlaybackState::Playing);
+					},
+					ControlMsg::Pause => {
+						paused.store(true, Ordering::Relaxed);
+						thread_state.set(PlaybackState::Paused);
+					},
+					ControlMsg::Stop | ControlMsg::Finish => break
+				}
+			}
+
+			// Deactivating hands back the process handler, dropping
+			// which hands the graph back to the control threads
+			if let Err(e) = active_client.deactivate() {
+				error!("Could not deactivate JACK client: {}", e);
+			}
+			thread_state.set(PlaybackState::Stopped);
+			info!("Stopped the JACK engine");
+		});
+
+		Ok(BackgroundEngine {
+			sample_rate: sample_rate,
+			channels: STANDARD_CHANNELS,
+			device: Some(client_name),
+			controls: EngineControls::new(control_sender),
+			state: state,
+			stats: stats
+		})
+	}
+}
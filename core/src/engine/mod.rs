@@ -2,16 +2,35 @@
 //! the audio graph and the sinks.
 
 pub mod speaker;
+pub mod file;
+pub mod null;
+pub mod pipe;
+#[cfg(feature = "jack")]
+pub mod jack;
+pub mod routing;
+pub mod dither;
+pub mod stats;
 
-use std::sync::mpsc;
+use std::sync::{Arc, mpsc};
+use std::sync::atomic::{AtomicUsize, AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
 use dsp::Node;
-use crate::graph::SharedAudioGraph;
+use crate::graph::GraphRenderer;
 use crate::audioformat::StandardFrame;
+use crate::util::exhaustible::Exhaustible;
+use crate::util::retargetable::Retargetable;
+use self::stats::EngineStats;
 
 /// A blocking audio playing engine.
 pub trait AudioEngine {
-	/// Runs the engine on a background thread.
-	fn run_async<N>(self, shared_graph: SharedAudioGraph<N>) -> BackgroundEngine where N: Node<StandardFrame> + Send + 'static;
+	/// Runs the engine on a background thread, which
+	/// owns the graph's renderer until the engine stops.
+	/// Fails if the output could not be set up.
+	/// 
+	/// Engines whose output sample rate can change
+	/// while running retarget the graph accordingly.
+	fn run_async<N>(self, renderer: GraphRenderer<N>) -> Result<BackgroundEngine, String> where N: Node<StandardFrame> + Exhaustible + Retargetable + Send + 'static;
 }
 
 /// Represents an engine running asynchronously
@@ -19,9 +38,48 @@ pub trait AudioEngine {
 /// starting the engine.
 #[derive(Clone)]
 pub struct BackgroundEngine {
-	/// The output sample rate
-	pub sample_hz: f64,
-	pub controls: EngineControls
+	/// The output sample rate, which may change
+	/// while the engine is running
+	pub sample_rate: Arc<SharedSampleRate>,
+	/// The number of output channels
+	pub channels: usize,
+	/// The name of the output device, if any
+	pub device: Option<String>,
+	pub controls: EngineControls,
+	/// The playback state as reported by the engine
+	pub state: Arc<SharedPlaybackState>,
+	/// Runtime statistics updated by the engine
+	pub stats: Arc<EngineStats>
+}
+
+/// The interval at which the playback state is polled
+/// while waiting for the engine to stop.
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+impl BackgroundEngine {
+	/// The current output sample rate.
+	pub fn sample_hz(&self) -> f64 { self.sample_rate.get() }
+	
+	/// Blocks until the engine reports that it has
+	/// stopped (and e.g. finalized its output).
+	pub fn wait_until_stopped(&self) {
+		while self.state.get() != PlaybackState::Stopped {
+			thread::sleep(STOP_POLL_INTERVAL);
+		}
+	}
+	
+	/// Blocks until the engine reports that it has stopped
+	/// or the timeout elapses, returning whether it stopped.
+	pub fn wait_until_stopped_for(&self, timeout: Duration) -> bool {
+		let deadline = Instant::now() + timeout;
+		while self.state.get() != PlaybackState::Stopped {
+			if Instant::now() >= deadline {
+				return false;
+			}
+			thread::sleep(STOP_POLL_INTERVAL);
+		}
+		true
+	}
 }
 
 /// A single control operation which can be
@@ -30,7 +88,74 @@ pub struct BackgroundEngine {
 pub enum ControlMsg {
 	Pause,
 	Play,
-	Custom(String)
+	/// Shuts the engine down, after which it will
+	/// not accept further messages. The output device
+	/// is released, but the speaker engine's thread
+	/// keeps idling, since CPAL's event loop can not
+	/// be ended.
+	Stop,
+	/// Shuts the engine down once it has played
+	/// everything it can. Real-time engines treat
+	/// this like `Stop`.
+	Finish
+}
+
+/// The playback state of an engine.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PlaybackState {
+	Playing,
+	Paused,
+	Stopped
+}
+
+/// A playback state that can be updated by
+/// the engine and read from other threads
+/// without locking.
+#[derive(Debug)]
+pub struct SharedPlaybackState {
+	state: AtomicUsize
+}
+
+impl SharedPlaybackState {
+	pub fn new(state: PlaybackState) -> SharedPlaybackState {
+		SharedPlaybackState { state: AtomicUsize::new(state as usize) }
+	}
+	
+	pub fn get(&self) -> PlaybackState {
+		match self.state.load(Ordering::Acquire) {
+			0 => PlaybackState::Playing,
+			1 => PlaybackState::Paused,
+			_ => PlaybackState::Stopped
+		}
+	}
+	
+	pub fn set(&self, state: PlaybackState) {
+		self.state.store(state as usize, Ordering::Release);
+	}
+}
+
+/// A sample rate that can be updated by the
+/// engine (e.g. when the JACK server switches
+/// rates) and read from other threads without
+/// locking.
+#[derive(Debug)]
+pub struct SharedSampleRate {
+	/// The bits of the rate in Hz
+	hz_bits: AtomicU64
+}
+
+impl SharedSampleRate {
+	pub fn new(sample_hz: f64) -> SharedSampleRate {
+		SharedSampleRate { hz_bits: AtomicU64::new(sample_hz.to_bits()) }
+	}
+	
+	pub fn get(&self) -> f64 {
+		f64::from_bits(self.hz_bits.load(Ordering::Acquire))
+	}
+	
+	pub fn set(&self, sample_hz: f64) {
+		self.hz_bits.store(sample_hz.to_bits(), Ordering::Release);
+	}
 }
 
 /// A wrapper around an MPSC channel that
@@ -50,8 +175,13 @@ impl EngineControls {
 		EngineControls { tx: tx }
 	}
 	
-	/// Sends a control message to the engine.
-	pub fn send(&self, msg: ControlMsg) {
-		self.tx.send(msg).expect("Could not send control message")
+	/// Sends a control message to the engine without
+	/// blocking. Fails if the engine has been stopped or
+	/// does not process its messages.
+	pub fn send(&self, msg: ControlMsg) -> Result<(), String> {
+		self.tx.try_send(msg).map_err(|e| match e {
+			mpsc::TrySendError::Full(..) => "The engine is not responding to control messages".to_owned(),
+			mpsc::TrySendError::Disconnected(..) => "The engine has been stopped".to_owned()
+		})
 	}
 }
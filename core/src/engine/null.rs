@@ -0,0 +1,101 @@
+use super::{AudioEngine, BackgroundEngine, ControlMsg, EngineControls, PlaybackState, SharedPlaybackState, SharedSampleRate};
+use super::stats::EngineStats;
+use crate::audioformat::{StandardFrame, STANDARD_CHANNELS};
+use crate::graph::GraphRenderer;
+use crate::util::exhaustible::Exhaustible;
+use crate::util::retargetable::Retargetable;
+use std::sync::{Arc, mpsc};
+use std::thread;
+use std::time::{Duration, Instant};
+use log::info;
+use dsp::{Frame, Node};
+
+/// The number of frames pulled from
+/// the graph at once.
+const CHUNK_FRAMES: usize = 512;
+
+/// An engine that pulls audio from the graph at
+/// (a multiple of) real-time pace and discards it.
+/// Useful for headless servers and testing.
+pub struct NullEngine {
+	sample_hz: f64,
+	speed: f64
+}
+
+impl NullEngine {
+	/// Creates a new null engine consuming the graph
+	/// at the given sample rate, `speed` times as
+	/// fast as real time.
+	pub fn new(sample_hz: f64, speed: f64) -> Result<NullEngine, String> {
+		if !(sample_hz >= 1.0) {
+			return Err(format!("Invalid sample rate {}", sample_hz));
+		}
+		if !(speed > 0.0 && speed.is_finite()) {
+			return Err(format!("Invalid speed {}, needs to be positive", speed));
+		}
+		Ok(NullEngine { sample_hz: sample_hz, speed: speed })
+	}
+}
+
+impl AudioEngine for NullEngine {
+	fn run_async<N>(self, mut renderer: GraphRenderer<N>) -> Result<BackgroundEngine, String> where N: Node<StandardFrame> + Exhaustible + Retargetable + Send + 'static {
+		let sample_hz = self.sample_hz;
+		let chunk_duration = Duration::from_secs_f64(CHUNK_FRAMES as f64 / (sample_hz * self.speed));
+		let (control_sender, control_receiver) = mpsc::sync_channel(5);
+		let state = Arc::new(SharedPlaybackState::new(PlaybackState::Playing));
+		let thread_state = state.clone();
+		let stats = Arc::new(EngineStats::default());
+		let chunk_stats = stats.clone();
+
+		thread::spawn(move || {
+			let mut paused = false;
+			let mut buffer = vec![StandardFrame::equilibrium(); CHUNK_FRAMES];
+			let mut deadline = Instant::now();
+
+			loop {
+				// Possibly receive a control operation message
+				if let Ok(msg) = control_receiver.try_recv() {
+					match msg {
+						ControlMsg::Play => {
+							paused = false;
+							thread_state.set(PlaybackState::Playing);
+						},
+						ControlMsg::Pause => {
+							paused = true;
+							thread_state.set(PlaybackState::Paused);
+						},
+						ControlMsg::Stop | ControlMsg::Finish => break
+					}
+				}
+
+				// Apply the graph modifications, even while paused
+				let chunk_start = Instant::now();
+				renderer.update();
+				let update_time = chunk_start.elapsed();
+				if !paused {
+					renderer.render(&mut buffer, sample_hz);
+					chunk_stats.record_callback(CHUNK_FRAMES, chunk_start.elapsed(), update_time, chunk_duration);
+				}
+
+				// Schedule relative to the previous deadline
+				// to avoid accumulating drift, but do not try
+				// to catch up after falling behind
+				let now = Instant::now();
+				deadline = (deadline + chunk_duration).max(now);
+				thread::sleep(deadline - now);
+			}
+
+			thread_state.set(PlaybackState::Stopped);
+			info!("Stopped the null engine");
+		});
+
+		Ok(BackgroundEngine {
+			sample_rate: Arc::new(SharedSampleRate::new(sample_hz)),
+			channels: STANDARD_CHANNELS,
+			device: None,
+			controls: EngineControls::new(control_sender),
+			state: state,
+			stats: stats
+		})
+	}
+}
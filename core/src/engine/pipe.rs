@@ -0,0 +1,241 @@
+use super::{AudioEngine, BackgroundEngine, ControlMsg, EngineControls, PlaybackState, SharedPlaybackState, SharedSampleRate};
+use super::stats::EngineStats;
+use crate::audioformat::{StandardFrame, StandardSample, STANDARD_CHANNELS};
+use crate::graph::GraphRenderer;
+use crate::util::exhaustible::Exhaustible;
+use crate::util::retargetable::Retargetable;
+use std::fs::OpenOptions;
+use std::io::{self, BufWriter, Write};
+#[cfg(unix)]
+use std::fs::File;
+#[cfg(unix)]
+use std::mem::ManuallyDrop;
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, mpsc};
+use std::sync::mpsc::RecvTimeoutError;
+use std::thread;
+use std::time::{Duration, Instant};
+use log::{info, error};
+use byteorder::{LittleEndian, WriteBytesExt};
+use dsp::{Frame, Node};
+
+/// The number of frames rendered and
+/// written at once.
+const CHUNK_FRAMES: usize = 1024;
+
+/// The interval at which graph modifications are
+/// applied while waiting for the output to open.
+const OPEN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The encoding of the raw PCM output.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PcmFormat {
+	/// 32-bit little-endian floats
+	F32Le,
+	/// 16-bit little-endian signed integers
+	S16Le
+}
+
+impl PcmFormat {
+	fn write_sample(self, sample: StandardSample, output: &mut Vec<u8>) {
+		let result = match self {
+			PcmFormat::F32Le => output.write_f32::<LittleEndian>(sample),
+			PcmFormat::S16Le => output.write_i16::<LittleEndian>((sample.max(-1.0).min(1.0) * i16::max_value() as StandardSample) as i16)
+		};
+		result.expect("Writing to a vector does not fail")
+	}
+
+	fn bytes_per_sample(self) -> usize {
+		match self {
+			PcmFormat::F32Le => 4,
+			PcmFormat::S16Le => 2
+		}
+	}
+}
+
+impl FromStr for PcmFormat {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<PcmFormat, String> {
+		match s {
+			"f32le" => Ok(PcmFormat::F32Le),
+			"s16le" => Ok(PcmFormat::S16Le),
+			_ => Err(format!("Unsupported PCM format '{}', try f32le or s16le", s))
+		}
+	}
+}
+
+/// Where the PCM stream is written to.
+#[derive(Debug, Clone)]
+pub enum PipeOutput {
+	Stdout,
+	/// A file or named pipe (FIFO)
+	Path(PathBuf)
+}
+
+impl PipeOutput {
+	/// Opens the output, which is buffered and
+	/// should be flushed once per chunk.
+	fn open(&self) -> io::Result<Box<dyn Write>> {
+		match *self {
+			PipeOutput::Stdout => Ok(Box::new(BufWriter::new(raw_stdout()))),
+			PipeOutput::Path(ref path) => Ok(Box::new(BufWriter::new(OpenOptions::new().write(true).create(true).truncate(true).open(path)?)))
+		}
+	}
+}
+
+/// The standard output without the line buffering
+/// of `io::Stdout`, which would split the binary
+/// stream at every newline byte.
+#[cfg(unix)]
+struct RawStdout {
+	/// The file descriptor is borrowed, thus
+	/// it must not be closed on drop
+	file: ManuallyDrop<File>
+}
+
+#[cfg(unix)]
+impl Write for RawStdout {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> { self.file.write(buf) }
+	
+	fn flush(&mut self) -> io::Result<()> { self.file.flush() }
+}
+
+#[cfg(unix)]
+fn raw_stdout() -> RawStdout {
+	// Safe since the descriptor remains open for the
+	// lifetime of the process and is never closed here
+	RawStdout { file: ManuallyDrop::new(unsafe { File::from_raw_fd(io::stdout().as_raw_fd()) }) }
+}
+
+#[cfg(not(unix))]
+fn raw_stdout() -> io::Stdout {
+	// Elsewhere, fall back to the
+	// line-buffered standard output
+	io::stdout()
+}
+
+/// An engine that writes the master output as interleaved
+/// raw PCM, e.g. to feed it into ffmpeg or sox.
+///
+/// Without real-time pacing, the engine renders as fast
+/// as the consumer reads. While paused, silence is written
+/// to keep the stream continuous.
+#[derive(Debug, Clone)]
+pub struct PipeEngine {
+	pub output: PipeOutput,
+	pub format: PcmFormat,
+	pub sample_hz: f64,
+	/// Whether the output is paced to real time
+	pub realtime: bool
+}
+
+impl AudioEngine for PipeEngine {
+	fn run_async<N>(self, mut renderer: GraphRenderer<N>) -> Result<BackgroundEngine, String> where N: Node<StandardFrame> + Exhaustible + Retargetable + Send + 'static {
+		let sample_hz = self.sample_hz;
+		let chunk_duration = Duration::from_secs_f64(CHUNK_FRAMES as f64 / sample_hz);
+		let (control_sender, control_receiver) = mpsc::sync_channel(5);
+		let stats = Arc::new(EngineStats::default());
+		let chunk_stats = stats.clone();
+		let state = Arc::new(SharedPlaybackState::new(PlaybackState::Playing));
+		let thread_state = state.clone();
+		let device = match self.output {
+			PipeOutput::Stdout => "stdout".to_owned(),
+			PipeOutput::Path(ref path) => path.display().to_string()
+		};
+
+		thread::spawn(move || {
+			// Opening a FIFO blocks until a reader connects, thus
+			// we do so on yet another thread while applying graph
+			// modifications
+			let (output_sender, output_receiver) = mpsc::channel();
+			let pipe_output = self.output.clone();
+			thread::spawn(move || {
+				let _ = output_sender.send(pipe_output.open());
+			});
+			let mut output = loop {
+				renderer.update();
+				if let Ok(ControlMsg::Stop) | Ok(ControlMsg::Finish) = control_receiver.try_recv() {
+					thread_state.set(PlaybackState::Stopped);
+					return;
+				}
+				match output_receiver.recv_timeout(OPEN_POLL_INTERVAL) {
+					Ok(Ok(output)) => break output,
+					Ok(Err(e)) => {
+						error!("Could not open PCM output: {}", e);
+						thread_state.set(PlaybackState::Stopped);
+						return;
+					},
+					Err(RecvTimeoutError::Timeout) => {},
+					Err(RecvTimeoutError::Disconnected) => unreachable!("The opening thread sends its result")
+				}
+			};
+			let mut paused = false;
+			let mut buffer = vec![StandardFrame::equilibrium(); CHUNK_FRAMES];
+			let mut bytes = Vec::with_capacity(CHUNK_FRAMES * STANDARD_CHANNELS * self.format.bytes_per_sample());
+			let mut deadline = Instant::now();
+
+			loop {
+				// Possibly receive a control operation message
+				if let Ok(msg) = control_receiver.try_recv() {
+					match msg {
+						ControlMsg::Play => {
+							paused = false;
+							thread_state.set(PlaybackState::Playing);
+						},
+						ControlMsg::Pause => {
+							paused = true;
+							thread_state.set(PlaybackState::Paused);
+						},
+						ControlMsg::Stop | ControlMsg::Finish => break
+					}
+				}
+
+				// Apply the graph modifications, even while paused
+				let chunk_start = Instant::now();
+				renderer.update();
+				let update_time = chunk_start.elapsed();
+				if paused {
+					for frame in buffer.iter_mut() {
+						*frame = StandardFrame::equilibrium();
+					}
+				} else {
+					renderer.render(&mut buffer, sample_hz);
+					chunk_stats.record_callback(CHUNK_FRAMES, chunk_start.elapsed(), update_time, chunk_duration);
+				}
+
+				bytes.clear();
+				for frame in &buffer {
+					for &sample in frame.iter() {
+						self.format.write_sample(sample, &mut bytes);
+					}
+				}
+				if let Err(e) = output.write_all(&bytes).and_then(|()| output.flush()) {
+					// Usually the consumer has closed the pipe
+					info!("Stopped writing PCM output: {}", e);
+					break;
+				}
+
+				if self.realtime {
+					let now = Instant::now();
+					deadline = (deadline + chunk_duration).max(now);
+					thread::sleep(deadline - now);
+				}
+			}
+
+			thread_state.set(PlaybackState::Stopped);
+		});
+
+		Ok(BackgroundEngine {
+			sample_rate: Arc::new(SharedSampleRate::new(sample_hz)),
+			channels: STANDARD_CHANNELS,
+			device: Some(device),
+			controls: EngineControls::new(control_sender),
+			state: state,
+			stats: stats
+		})
+	}
+}
@@ -0,0 +1,97 @@
+//! Routing of the (stereo) standard frame
+//! onto the channels of an output device.
+
+use crate::audioformat::{StandardFrame, StandardSample};
+use dsp::{Sample, FromSample};
+use std::str::FromStr;
+
+/// The signal that a single output
+/// channel plays.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ChannelRoute {
+	Left,
+	Right,
+	/// The average of both channels
+	Mix,
+	Silent
+}
+
+impl ChannelRoute {
+	fn apply(self, frame: StandardFrame) -> StandardSample {
+		match self {
+			ChannelRoute::Left => frame[0],
+			ChannelRoute::Right => frame[1],
+			ChannelRoute::Mix => (frame[0] + frame[1]) * 0.5,
+			ChannelRoute::Silent => 0.0
+		}
+	}
+}
+
+impl FromStr for ChannelRoute {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<ChannelRoute, String> {
+		match s.trim() {
+			"L" | "l" => Ok(ChannelRoute::Left),
+			"R" | "r" => Ok(ChannelRoute::Right),
+			"M" | "m" => Ok(ChannelRoute::Mix),
+			"-" => Ok(ChannelRoute::Silent),
+			_ => Err(format!("Invalid channel route '{}', try one of L, R, M or -", s))
+		}
+	}
+}
+
+/// Maps stereo frames onto a device's channels,
+/// one route per output channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChannelMap {
+	routes: Vec<ChannelRoute>
+}
+
+impl ChannelMap {
+	pub fn new(routes: Vec<ChannelRoute>) -> ChannelMap {
+		ChannelMap { routes: routes }
+	}
+
+	/// The default layout for the given number of output
+	/// channels. Surround layouts follow the WAV channel order
+	/// and play the stereo signal on both the front and the
+	/// rear speakers.
+	pub fn default_for(channels: usize) -> ChannelMap {
+		use ChannelRoute::*;
+		ChannelMap::new(match channels {
+			1 => vec![Mix],
+			2 => vec![Left, Right],
+			4 => vec![Left, Right, Left, Right],
+			6 => vec![Left, Right, Mix, Silent, Left, Right],
+			8 => vec![Left, Right, Mix, Silent, Left, Right, Left, Right],
+			_ => (0..channels).map(|i| if i % 2 == 0 { Left } else { Right }).collect()
+		})
+	}
+
+	/// The number of output channels.
+	pub fn channels(&self) -> usize { self.routes.len() }
+
+	/// Whether this map passes stereo
+	/// frames through unchanged.
+	pub fn is_identity(&self) -> bool { self.routes == [ChannelRoute::Left, ChannelRoute::Right] }
+
+	/// Writes a single frame into the (interleaved)
+	/// output frame, which needs to contain exactly
+	/// `channels()` samples.
+	pub fn map<S>(&self, frame: StandardFrame, output: &mut [S]) where S: Sample + FromSample<StandardSample> {
+		for (sample, route) in output.iter_mut().zip(self.routes.iter()) {
+			*sample = route.apply(frame).to_sample();
+		}
+	}
+}
+
+impl FromStr for ChannelMap {
+	type Err = String;
+
+	/// Parses a comma-separated list of routes,
+	/// such as `L,R,L,R`.
+	fn from_str(s: &str) -> Result<ChannelMap, String> {
+		s.split(',').map(ChannelRoute::from_str).collect::<Result<Vec<_>, _>>().map(ChannelMap::new)
+	}
+}
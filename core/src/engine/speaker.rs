@@ -1,13 +1,23 @@
-use super::{AudioEngine, BackgroundEngine, ControlMsg, EngineControls};
-use crate::audioformat::{StandardFrame, STANDARD_CHANNELS, StandardSample};
-use crate::graph::SharedAudioGraph;
-use std::sync::mpsc;
+use super::{AudioEngine, BackgroundEngine, ControlMsg, EngineControls, PlaybackState, SharedPlaybackState, SharedSampleRate};
+use super::routing::ChannelMap;
+use super::stats::EngineStats;
+use super::dither::TpdfDither;
+use crate::audioformat::{StandardFrame, StandardSample};
+use crate::graph::GraphRenderer;
+use crate::util::exhaustible::Exhaustible;
+use crate::util::retargetable::Retargetable;
+use std::sync::{Arc, mpsc};
 use std::thread;
-use log::{info, error};
+use std::time::{Duration, Instant};
+use log::{info, warn, error};
 use dsp::{Sample, Frame, FromSample, Node, sample::conv::ToFrameSliceMut};
-use cpal::{StreamData, UnknownTypeOutputBuffer, OutputBuffer};
+use cpal::{StreamData, UnknownTypeOutputBuffer, OutputBuffer, Host, Device, Format, SampleRate, SampleFormat};
 use cpal::traits::{DeviceTrait, EventLoopTrait, HostTrait};
 
+/// The number of frames preallocated for
+/// converting output.
+const DEFAULT_SCRATCH_FRAMES: usize = 8192;
+
 macro_rules! with_buffer_of {
 	($data: expr, $body: expr) => {
 		match $data {
@@ -19,69 +29,264 @@ macro_rules! with_buffer_of {
 	};
 }
 
+/// An output format supported by a device.
+#[derive(Debug, Clone)]
+pub struct FormatInfo {
+	pub channels: u16,
+	pub min_sample_hz: u32,
+	pub max_sample_hz: u32,
+	pub sample_format: String
+}
+
+/// An output device and its supported formats.
+#[derive(Debug, Clone)]
+pub struct DeviceInfo {
+	pub name: String,
+	pub is_default: bool,
+	pub formats: Vec<FormatInfo>
+}
+
+/// An audio host (backend, such as ALSA or WASAPI)
+/// and its output devices.
+#[derive(Debug, Clone)]
+pub struct HostInfo {
+	pub name: String,
+	pub is_default: bool,
+	pub devices: Vec<DeviceInfo>
+}
+
+/// Enumerates the available hosts, their output
+/// devices and the formats supported by these.
+pub fn list_devices() -> Vec<HostInfo> {
+	let default_host_id = cpal::default_host().id();
+	cpal::available_hosts().into_iter()
+		.filter_map(|host_id| match cpal::host_from_id(host_id) {
+			Ok(host) => Some(host),
+			Err(e) => {
+				warn!("Host {} is unavailable: {}", host_id.name(), e);
+				None
+			}
+		})
+		.map(|host| {
+			let default_device_name = host.default_output_device().and_then(|d| d.name().ok());
+			let devices = match host.output_devices() {
+				Ok(devices) => devices.filter_map(|device| {
+					let name = device.name().ok()?;
+					let formats = device.supported_output_formats()
+						.map(|formats| formats.map(|f| FormatInfo {
+							channels: f.channels,
+							min_sample_hz: f.min_sample_rate.0,
+							max_sample_hz: f.max_sample_rate.0,
+							sample_format: format!("{:?}", f.data_type)
+						}).collect())
+						.unwrap_or_else(|_| Vec::new());
+					Some(DeviceInfo { is_default: default_device_name.as_ref() == Some(&name), name: name, formats: formats })
+				}).collect(),
+				Err(e) => {
+					warn!("Could not enumerate output devices of {}: {}", host.id().name(), e);
+					Vec::new()
+				}
+			};
+			HostInfo { name: host.id().name().to_owned(), is_default: host.id() == default_host_id, devices: devices }
+		})
+		.collect()
+}
+
 /// An engine that uses CPAL to provide
 /// cross-platform audio output.
-pub struct SpeakerEngine;
+///
+/// Unless specified otherwise, the default host,
+/// output device and format are used.
+///
+/// Every run spawns a thread for CPAL's event loop,
+/// which never returns. Once the engine is stopped,
+/// the stream is destroyed and the thread idles, thus
+/// the engine can be run again (e.g. on another device).
+#[derive(Debug, Clone, Default)]
+pub struct SpeakerEngine {
+	/// The name of the audio host
+	pub host: Option<String>,
+	/// The name of the output device
+	pub device: Option<String>,
+	/// The requested output sample rate
+	pub sample_hz: Option<u32>,
+	/// Routes the stereo output onto the device's
+	/// channels (defaults to a layout depending on
+	/// the channel count)
+	pub channel_map: Option<ChannelMap>,
+	/// Whether TPDF dither is applied when
+	/// outputting 16-bit integer samples
+	pub dither: bool
+}
+
+impl SpeakerEngine {
+	fn select_host(&self) -> Result<Host, String> {
+		match self.host {
+			Some(ref name) => {
+				let host_id = cpal::available_hosts().into_iter()
+					.find(|id| id.name().eq_ignore_ascii_case(name))
+					.ok_or_else(|| format!("No host named '{}' is available", name))?;
+				cpal::host_from_id(host_id).map_err(|e| format!("Could not open host '{}': {}", name, e))
+			},
+			None => Ok(cpal::default_host())
+		}
+	}
+	
+	fn select_device(&self, host: &Host) -> Result<Device, String> {
+		match self.device {
+			Some(ref name) => host.output_devices()
+				.map_err(|e| format!("Could not enumerate output devices: {}", e))?
+				.find(|d| d.name().map(|n| &n == name).unwrap_or(false))
+				.ok_or_else(|| format!("No output device named '{}' is available", name)),
+			None => host.default_output_device().ok_or_else(|| "No output device available".to_owned())
+		}
+	}
+	
+	fn select_format(&self, device: &Device) -> Result<Format, String> {
+		let default_format = device.default_output_format().map_err(|e| format!("No default format: {}", e))?;
+		match self.sample_hz {
+			Some(sample_hz) => {
+				// Prefer a format that matches the default
+				// one in everything except the sample rate
+				let mut formats: Vec<_> = device.supported_output_formats()
+					.map_err(|e| format!("Could not query supported formats: {}", e))?
+					.filter(|f| f.min_sample_rate.0 <= sample_hz && sample_hz <= f.max_sample_rate.0)
+					.collect();
+				formats.sort_by_key(|f| (f.channels != default_format.channels, f.data_type != default_format.data_type));
+				formats.into_iter().next()
+					.map(|f| Format { channels: f.channels, sample_rate: SampleRate(sample_hz), data_type: f.data_type })
+					.ok_or_else(|| format!("The output device does not support a sample rate of {} Hz", sample_hz))
+			},
+			None => Ok(default_format)
+		}
+	}
+}
 
 impl AudioEngine for SpeakerEngine {
-	fn run_async<N>(self, shared_graph: SharedAudioGraph<N>) -> BackgroundEngine where N: Node<StandardFrame> + Send + 'static {
+	fn run_async<N>(self, mut renderer: GraphRenderer<N>) -> Result<BackgroundEngine, String> where N: Node<StandardFrame> + Exhaustible + Retargetable + Send + 'static {
 		// Setup CPAL
-		let host = cpal::default_host();
-		let event_loop = host.event_loop();
-		let device = host.default_output_device().expect("No output device available.");
-		let format = device.default_output_format().expect("No default format.");
+		let host = self.select_host()?;
+		let event_loop = Arc::new(host.event_loop());
+		let device = self.select_device(&host)?;
+		let format = self.select_format(&device)?;
+		let device_name = device.name().ok();
+		info!("Playing on '{}' using {:?}", device_name.as_ref().map(|n| n.as_str()).unwrap_or("?"), format);
 		let sample_hz = format.sample_rate.0 as f64;
 		let channels = format.channels as usize;
-		let stream_id = event_loop.build_output_stream(&device, &format).expect("Could not build output stream");
-		
-		if channels != STANDARD_CHANNELS {
-			// TODO
-			error!("CPAL output format has {} channels, but only exactly {} are supported currently.", channels, STANDARD_CHANNELS);
+		let channel_map = self.channel_map.clone().unwrap_or_else(|| ChannelMap::default_for(channels));
+		if channel_map.channels() != channels {
+			return Err(format!("The channel map routes {} channels, but the output device has {}", channel_map.channels(), channels));
 		}
+		let stream_id = event_loop.build_output_stream(&device, &format).map_err(|e| format!("Could not build output stream: {}", e))?;
+		
+		// When restarting, the graph may have been
+		// rendered at another rate before
+		renderer.retarget(sample_hz);
+		
+		// Preallocate the buffer used to convert the output to
+		// avoid allocating in the audio callback. CPAL does not
+		// tell us the buffer size before playing, thus larger
+		// buffers are rendered in chunks.
+		let mut scratch = vec![StandardFrame::equilibrium(); DEFAULT_SCRATCH_FRAMES];
+		let mut dither = match format.data_type {
+			SampleFormat::I16 | SampleFormat::U16 if self.dither => Some(TpdfDither::new(16)),
+			_ => None
+		};
 		
 		let (control_sender, control_receiver) = mpsc::sync_channel(5);
-		event_loop.play_stream(stream_id.clone()).expect("Could not play stream.");
+		let stats = Arc::new(EngineStats::default());
+		let callback_stats = stats.clone();
+		let state = Arc::new(SharedPlaybackState::new(PlaybackState::Playing));
+		let callback_state = state.clone();
+		event_loop.play_stream(stream_id.clone()).map_err(|e| format!("Could not play stream: {}", e))?;
 
 		thread::spawn(move || {
 			let mut paused = false;
+			let mut control_receiver = Some(control_receiver);
+			let mut graph_renderer = Some(renderer);
+			let callback_event_loop = event_loop.clone();
 
 			event_loop.run(move |_id, result| {
-				let mut data = result.expect("Error while streaming.");
+				let renderer = match graph_renderer {
+					Some(ref mut renderer) => renderer,
+					// The engine has been stopped
+					None => return
+				};
+				
+				let callback_start = Instant::now();
+				let mut data = match result {
+					Ok(data) => data,
+					Err(e) => {
+						callback_stats.record_stream_error();
+						error!("Error while streaming: {}", e);
+						// Keep applying the graph modifications,
+						// which control threads are waiting for
+						renderer.update();
+						return;
+					}
+				};
 				
 				// Possibly receive a control operation message
-				if let Ok(msg) = control_receiver.try_recv() {
+				let msg = control_receiver.as_ref().and_then(|receiver| receiver.try_recv().ok());
+				if let Some(msg) = msg {
 					match msg {
-						ControlMsg::Play => paused = false,
+						ControlMsg::Play => {
+							paused = false;
+							callback_state.set(PlaybackState::Playing);
+						},
 						ControlMsg::Pause => {
 							with_buffer_of!(data, write_silence);
-							paused = true
+							paused = true;
+							callback_state.set(PlaybackState::Paused);
+						},
+						ControlMsg::Stop | ControlMsg::Finish => {
+							// CPAL's event loop never returns, thus we can only
+							// release the device and stop processing the graph.
+							// Dropping the receiver makes further sends fail and
+							// dropping the renderer hands the graph back.
+							with_buffer_of!(data, write_silence);
+							callback_event_loop.destroy_stream(stream_id.clone());
+							control_receiver = None;
+							graph_renderer = None;
+							callback_state.set(PlaybackState::Stopped);
+							info!("Stopped the speaker engine");
+							return;
 						}
-						_ => info!("Control message not recognized by the speaker/CPAL engine: {:?}", msg)
 					}
 				}
 			
+				// Apply the graph modifications, even while paused
+				let update_start = Instant::now();
+				renderer.update();
+				let update_time = update_start.elapsed();
+			
 				if !paused {
 					// Play the audio
-					if let StreamData::Output { buffer: UnknownTypeOutputBuffer::F32(ref mut buffer) } = data {
-						// Our speaker format matches the internal format, thus we do
-						// not need to allocate an extra vector
-						let buf_slice: &mut [StandardFrame] = buffer.to_frame_slice_mut().unwrap();
-						shared_graph.lock().audio_requested(buf_slice, sample_hz);
-					} else {
-						// Read audio from graph into temporary buffer
-						let sample_count = buffer_sample_count(&data).unwrap_or(0);
-						let frame_count = sample_count / STANDARD_CHANNELS;
-						// TODO: Allocate Vec once, then grow as needed
-						let mut audio: Vec<StandardFrame> = vec![StandardFrame::equilibrium(); frame_count];
-						shared_graph.lock().audio_requested(&mut audio, sample_hz);
-					
-						with_buffer_of!(data, |buffer| write_audio(&audio, buffer));
+					let frame_count = buffer_sample_count(&data).unwrap_or(0) / channels;
+					match data {
+						StreamData::Output { buffer: UnknownTypeOutputBuffer::F32(ref mut buffer) } if channel_map.is_identity() => {
+							// Our speaker format matches the internal format, thus we do
+							// not need to allocate an extra vector
+							let buf_slice: &mut [StandardFrame] = buffer.to_frame_slice_mut().unwrap();
+							renderer.render(buf_slice, sample_hz);
+						},
+						_ => with_buffer_of!(data, |buffer| render_converted(renderer, &mut scratch, &mut dither, &channel_map, sample_hz, buffer))
 					}
+					
+					let period = Duration::from_secs_f64(frame_count as f64 / sample_hz);
+					callback_stats.record_callback(frame_count, callback_start.elapsed(), update_time, period);
 				}
 			});
 		});
 
-		BackgroundEngine { sample_hz: sample_hz, controls: EngineControls::new(control_sender) }
+		Ok(BackgroundEngine {
+			sample_rate: Arc::new(SharedSampleRate::new(sample_hz)),
+			channels: channels,
+			device: device_name,
+			controls: EngineControls::new(control_sender),
+			state: state,
+			stats: stats
+		})
 	}
 }
 
@@ -95,11 +300,26 @@ fn buffer_sample_count(data: &StreamData) -> Option<usize> {
 	}
 }
 
-/// Writes the audio to the speaker in a specified format.
-fn write_audio<S>(audio: &[StandardFrame], buffer: &mut OutputBuffer<S>) where S: cpal::Sample + Sample + FromSample<StandardSample> {
-	let buf_slice: &mut [[S; STANDARD_CHANNELS]] = buffer.to_frame_slice_mut().unwrap();
-	for (i, frame) in buf_slice.iter_mut().enumerate() {
-		*frame = audio[i].map(Sample::to_sample);
+/// Writes the audio to the speaker in a specified format,
+/// routing it onto the device's channels.
+fn write_audio<S>(audio: &[StandardFrame], output: &mut [S], channel_map: &ChannelMap) where S: cpal::Sample + Sample + FromSample<StandardSample> {
+	for (frame, output) in audio.iter().zip(output.chunks_mut(channel_map.channels())) {
+		channel_map.map(*frame, output);
+	}
+}
+
+/// Renders the graph into an output buffer of another
+/// format (or channel layout) through the preallocated
+/// scratch buffer, in chunks if the output is larger.
+fn render_converted<N, S>(renderer: &mut GraphRenderer<N>, scratch: &mut [StandardFrame], dither: &mut Option<TpdfDither>, channel_map: &ChannelMap, sample_hz: f64, buffer: &mut OutputBuffer<S>) where N: Node<StandardFrame>, S: cpal::Sample + Sample + FromSample<StandardSample> {
+	let channels = channel_map.channels();
+	for output in buffer.chunks_mut(scratch.len() * channels) {
+		let audio = &mut scratch[..output.len() / channels];
+		renderer.render(audio, sample_hz);
+		if let Some(ref mut dither) = *dither {
+			dither.apply(audio);
+		}
+		write_audio(audio, output, channel_map);
 	}
 }
 
@@ -0,0 +1,95 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Runtime counters that an engine updates
+/// from its audio thread without locking.
+#[derive(Debug, Default)]
+pub struct EngineStats {
+	callbacks: AtomicU64,
+	late_callbacks: AtomicU64,
+	stream_errors: AtomicU64,
+	frames: AtomicU64,
+	total_callback_nanos: AtomicU64,
+	max_callback_nanos: AtomicU64,
+	total_update_nanos: AtomicU64,
+	max_update_nanos: AtomicU64
+}
+
+/// A point-in-time copy of an engine's statistics.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EngineStatsSnapshot {
+	/// The number of processed buffers
+	pub callbacks: u64,
+	/// The number of buffers that took longer to
+	/// compute than to play (i.e. likely underruns)
+	pub late_callbacks: u64,
+	/// The number of errors reported by the stream
+	pub stream_errors: u64,
+	/// The number of processed frames
+	pub frames: u64,
+	pub total_callback_secs: f64,
+	pub max_callback_secs: f64,
+	/// The time spent applying graph updates submitted
+	/// by control threads
+	pub total_update_secs: f64,
+	pub max_update_secs: f64
+}
+
+fn nanos(duration: Duration) -> u64 {
+	duration.as_secs() * 1_000_000_000 + duration.subsec_nanos() as u64
+}
+
+fn secs(nanos: u64) -> f64 {
+	nanos as f64 / 1_000_000_000.0
+}
+
+/// Atomically raises the value to at least `value`.
+fn store_max(atomic: &AtomicU64, value: u64) {
+	let mut current = atomic.load(Ordering::Relaxed);
+	while value > current {
+		match atomic.compare_exchange_weak(current, value, Ordering::Relaxed, Ordering::Relaxed) {
+			Ok(..) => break,
+			Err(actual) => current = actual
+		}
+	}
+}
+
+impl EngineStats {
+	/// Records a processed buffer of the given size,
+	/// taking `duration` to compute (of which `update`
+	/// were spent applying graph updates) and `period`
+	/// to play back.
+	pub fn record_callback(&self, frames: usize, duration: Duration, update: Duration, period: Duration) {
+		self.callbacks.fetch_add(1, Ordering::Relaxed);
+		self.frames.fetch_add(frames as u64, Ordering::Relaxed);
+		if duration > period {
+			self.late_callbacks.fetch_add(1, Ordering::Relaxed);
+		}
+
+		let duration_nanos = nanos(duration);
+		self.total_callback_nanos.fetch_add(duration_nanos, Ordering::Relaxed);
+		store_max(&self.max_callback_nanos, duration_nanos);
+
+		let update_nanos = nanos(update);
+		self.total_update_nanos.fetch_add(update_nanos, Ordering::Relaxed);
+		store_max(&self.max_update_nanos, update_nanos);
+	}
+
+	/// Records an error reported by the output stream.
+	pub fn record_stream_error(&self) {
+		self.stream_errors.fetch_add(1, Ordering::Relaxed);
+	}
+
+	pub fn snapshot(&self) -> EngineStatsSnapshot {
+		EngineStatsSnapshot {
+			callbacks: self.callbacks.load(Ordering::Relaxed),
+			late_callbacks: self.late_callbacks.load(Ordering::Relaxed),
+			stream_errors: self.stream_errors.load(Ordering::Relaxed),
+			frames: self.frames.load(Ordering::Relaxed),
+			total_callback_secs: secs(self.total_callback_nanos.load(Ordering::Relaxed)),
+			max_callback_secs: secs(self.max_callback_nanos.load(Ordering::Relaxed)),
+			total_update_secs: secs(self.total_update_nanos.load(Ordering::Relaxed)),
+			max_update_secs: secs(self.max_update_nanos.load(Ordering::Relaxed))
+		}
+	}
+}
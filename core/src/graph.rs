@@ -1,23 +1,791 @@
 use dsp::Node;
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::mem;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, Thread};
+use std::time::{Duration, Instant};
+use log::error;
 use parking_lot::Mutex;
+use crossbeam_queue::ArrayQueue;
 use crate::audioformat::StandardFrame;
 use crate::util::empty::Empty;
+use crate::util::retargetable::Retargetable;
 
 pub type NodeIndex = dsp::NodeIndex;
 pub type EdgeIndex = dsp::EdgeIndex;
-pub type AudioGraph<N> = dsp::Graph<StandardFrame, N>;
+pub type WouldCycle = dsp::WouldCycle;
 
-/// The audio graph which is shared between
-/// an engine and possibly control threads
-/// (such as RPC-mechanisms).
-pub type SharedAudioGraph<N> = Arc<Mutex<AudioGraph<N>>>;
+/// An identifier for a node that, unlike a
+/// `NodeIndex`, remains valid when other nodes
+/// are removed and is never reused.
+pub type NodeId = usize;
 
-/// Creates a new, shared atomically reference-counted
-/// audio graph with a single, empty master node.
-pub fn new_shared_graph<N>() -> SharedAudioGraph<N> where N: Empty + Node<StandardFrame> {
+/// An identifier for an edge that, unlike an
+/// `EdgeIndex`, remains valid when other edges
+/// or nodes are removed and is never reused.
+pub type EdgeId = usize;
+
+/// An audio graph that addresses nodes and
+/// edges by stable identifiers.
+///
+/// The underlying graph swap-removes nodes and
+/// edges, which invalidates the index of the last
+/// node/edge. Therefore this wrapper maps identifiers
+/// to indices and only exposes mutations that keep
+/// this mapping up to date.
+pub struct AudioGraph<N> {
+	graph: dsp::Graph<StandardFrame, N>,
+	/// The index of every node
+	node_indices: BTreeMap<NodeId, NodeIndex>,
+	/// The identifier of every node, by index
+	node_ids: Vec<NodeId>,
+	/// The endpoints of every edge, which (unlike
+	/// the edge indices) are unaffected by removals
+	edges: BTreeMap<EdgeId, (NodeId, NodeId)>,
+	/// The user-assigned names of the nodes, which
+	/// are shared to make copies cheap
+	names: BTreeMap<NodeId, Arc<str>>,
+	next_node_id: NodeId,
+	next_edge_id: EdgeId
+}
+
+impl<N> AudioGraph<N> where N: Node<StandardFrame> {
+	pub fn new() -> AudioGraph<N> {
+		AudioGraph {
+			graph: dsp::Graph::new(),
+			node_indices: BTreeMap::new(),
+			node_ids: Vec::new(),
+			edges: BTreeMap::new(),
+			names: BTreeMap::new(),
+			next_node_id: 0,
+			next_edge_id: 0
+		}
+	}
+	
+	/// Requests audio from the master node.
+	pub fn audio_requested(&mut self, buffer: &mut [StandardFrame], sample_hz: f64) {
+		self.graph.audio_requested(buffer, sample_hz);
+	}
+	
+	/// Looks up the current (internal)
+	/// index of a node.
+	pub fn index_of(&self, id: NodeId) -> Option<NodeIndex> {
+		self.node_indices.get(&id).cloned()
+	}
+	
+	pub fn contains_node(&self, id: NodeId) -> bool {
+		self.node_indices.contains_key(&id)
+	}
+	
+	pub fn node(&self, id: NodeId) -> Option<&N> {
+		self.index_of(id).and_then(|index| self.graph.node(index))
+	}
+	
+	pub fn node_mut(&mut self, id: NodeId) -> Option<&mut N> {
+		let index = self.index_of(id)?;
+		self.graph.node_mut(index)
+	}
+	
+	/// Iterates the nodes along with their identifiers.
+	pub fn nodes(&self) -> impl Iterator<Item=(NodeId, &N)> + '_ {
+		self.node_indices.iter().filter_map(move |(&id, &index)| self.graph.node(index).map(|node| (id, node)))
+	}
+	
+	pub fn name(&self, id: NodeId) -> Option<&str> {
+		self.names.get(&id).map(|name| name.as_ref())
+	}
+	
+	/// Iterates the names of the named nodes.
+	pub fn names(&self) -> impl Iterator<Item=(NodeId, &Arc<str>)> + '_ {
+		self.names.iter().map(|(&id, name)| (id, name))
+	}
+	
+	/// Names (or, given `None`, unnames) a node,
+	/// returning its previous name. Ignores
+	/// identifiers that do not exist.
+	pub fn set_name(&mut self, id: NodeId, name: Option<Arc<str>>) -> Option<Arc<str>> {
+		if !self.contains_node(id) {
+			return None;
+		}
+		match name {
+			Some(name) => self.names.insert(id, name),
+			None => self.names.remove(&id)
+		}
+	}
+	
+	pub fn master(&self) -> Option<NodeId> {
+		self.graph.master_index().map(|index| self.node_ids[index.index()])
+	}
+	
+	/// Sets the master node. Ignores
+	/// identifiers that do not exist.
+	pub fn set_master(&mut self, id: Option<NodeId>) {
+		let index = id.and_then(|id| self.index_of(id));
+		self.graph.set_master(index);
+	}
+	
+	pub fn add_node(&mut self, node: N) -> NodeId {
+		let id = self.next_node_id;
+		self.insert_node(id, node);
+		id
+	}
+	
+	fn insert_node(&mut self, id: NodeId, node: N) {
+		let index = self.graph.add_node(node);
+		self.next_node_id = self.next_node_id.max(id + 1);
+		self.node_indices.insert(id, index);
+		self.node_ids.push(id);
+		debug_assert_eq!(self.node_ids.len() - 1, index.index());
+	}
+	
+	/// Removes a node along with its edges.
+	pub fn remove_node(&mut self, id: NodeId) -> Option<N> {
+		let index = self.index_of(id)?;
+		let master = self.master();
+		let removed = self.graph.remove_node(index);
+		
+		// The underlying graph moves the last
+		// node into the freed index
+		self.node_indices.remove(&id);
+		self.names.remove(&id);
+		self.node_ids.swap_remove(index.index());
+		if let Some(&moved) = self.node_ids.get(index.index()) {
+			self.node_indices.insert(moved, index);
+		}
+		
+		let dangling: Vec<EdgeId> = self.edges.iter()
+			.filter(|&(_, &(src, dest))| src == id || dest == id)
+			.map(|(&edge_id, _)| edge_id)
+			.collect();
+		for edge_id in dangling {
+			self.edges.remove(&edge_id);
+		}
+		
+		self.set_master(master.filter(|&m| m != id));
+		removed
+	}
+	
+	/// Connects two nodes, returning the identifier of
+	/// the (possibly already existing) edge between them.
+	/// Both nodes are expected to exist.
+	pub fn add_edge(&mut self, src: NodeId, dest: NodeId) -> Result<EdgeId, WouldCycle> {
+		if let Some(id) = self.find_edge(src, dest) {
+			return Ok(id);
+		}
+		let id = self.next_edge_id;
+		self.insert_edge(id, src, dest)?;
+		Ok(id)
+	}
+	
+	fn insert_edge(&mut self, id: EdgeId, src: NodeId, dest: NodeId) -> Result<(), WouldCycle> {
+		let src_index = self.index_of(src).expect("Source node does not exist");
+		let dest_index = self.index_of(dest).expect("Destination node does not exist");
+		self.graph.add_connection(src_index, dest_index)?;
+		self.next_edge_id = self.next_edge_id.max(id + 1);
+		self.edges.insert(id, (src, dest));
+		Ok(())
+	}
+	
+	/// Removes an edge, returning its endpoints
+	/// if it existed.
+	pub fn remove_edge(&mut self, id: EdgeId) -> Option<(NodeId, NodeId)> {
+		let (src, dest) = self.edges.remove(&id)?;
+		if let (Some(src_index), Some(dest_index)) = (self.index_of(src), self.index_of(dest)) {
+			self.graph.remove_connection(src_index, dest_index);
+		}
+		Some((src, dest))
+	}
+	
+	/// Looks up the edge between two nodes.
+	pub fn find_edge(&self, src: NodeId, dest: NodeId) -> Option<EdgeId> {
+		self.edges.iter().find(|&(_, &endpoints)| endpoints == (src, dest)).map(|(&id, _)| id)
+	}
+	
+	/// Collects the given node and the nodes whose
+	/// output (directly or indirectly) feeds into it.
+	pub fn upstream_of(&self, id: NodeId) -> Vec<NodeId> {
+		let mut upstream = if self.contains_node(id) { vec![id] } else { Vec::new() };
+		let mut i = 0;
+		while i < upstream.len() {
+			let dest = upstream[i];
+			for &(src, edge_dest) in self.edges.values() {
+				if edge_dest == dest && !upstream.contains(&src) {
+					upstream.push(src);
+				}
+			}
+			i += 1;
+		}
+		upstream
+	}
+	
+	/// Iterates the edges along with their
+	/// source and destination nodes.
+	pub fn edges(&self) -> impl Iterator<Item=(EdgeId, NodeId, NodeId)> + '_ {
+		self.edges.iter().map(|(&id, &(src, dest))| (id, src, dest))
+	}
+	
+	/// Checks whether an edit can be applied, which is the
+	/// case unless it refers to missing nodes or edges, tries
+	/// to restore identifiers that are in use, or (which is only
+	/// detected when applying it) would create a cycle.
+	fn check(&self, edit: &GraphEdit<N>) -> Result<(), String> {
+		match *edit {
+			GraphEdit::AddNode { id: None, .. } => Ok(()),
+			GraphEdit::AddNode { id: Some(id), .. } => {
+				// Only previously issued identifiers may be restored
+				if id >= self.next_node_id || self.contains_node(id) {
+					return Err(format!("Node {} can not be restored", id));
+				}
+				Ok(())
+			},
+			GraphEdit::AddEdge { id, src, dest } => {
+				if !self.contains_node(src) || !self.contains_node(dest) {
+					return Err(format!("Edge {} -> {} connects nodes that do not exist", src, dest));
+				}
+				match (id, self.find_edge(src, dest)) {
+					(None, _) => Ok(()),
+					(Some(id), None) if id < self.next_edge_id && !self.edges.contains_key(&id) => Ok(()),
+					(Some(id), _) => Err(format!("Edge {} can not be restored", id))
+				}
+			},
+			GraphEdit::RemoveEdge { id } if !self.edges.contains_key(&id) => Err(format!("Edge {} does not exist", id)),
+			GraphEdit::RemoveEdge { .. } => Ok(()),
+			GraphEdit::SetMaster { id: None } => Ok(()),
+			GraphEdit::RemoveNode { id }
+				| GraphEdit::ReplaceNode { id, .. }
+				| GraphEdit::SetName { id, .. }
+				| GraphEdit::SetMaster { id: Some(id) } => {
+				if !self.contains_node(id) {
+					return Err(format!("Node {} does not exist", id));
+				}
+				Ok(())
+			}
+		}
+	}
+	
+	/// Applies a single edit, returning the
+	/// edits that revert it.
+	pub fn apply(&mut self, edit: GraphEdit<N>) -> Result<AppliedEdit<N>, EditError<N>> {
+		if let Err(message) = self.check(&edit) {
+			return Err(EditError { message: message, edits: vec![edit] });
+		}
+		let applied = match edit {
+			GraphEdit::AddNode { id: None, node } => {
+				let id = self.add_node(node);
+				AppliedEdit { created: Some(id), inverse: vec![GraphEdit::RemoveNode { id: id }] }
+			},
+			GraphEdit::AddNode { id: Some(id), node } => {
+				self.insert_node(id, node);
+				AppliedEdit { created: Some(id), inverse: vec![GraphEdit::RemoveNode { id: id }] }
+			},
+			GraphEdit::RemoveNode { id } => {
+				let master = self.master();
+				let name = self.names.get(&id).cloned();
+				let edges: Vec<_> = self.edges().filter(|&(_, src, dest)| src == id || dest == id).collect();
+				let node = self.remove_node(id).expect("The checked node should exist");
+				let mut inverse = vec![GraphEdit::AddNode { id: Some(id), node: node }];
+				if name.is_some() {
+					inverse.push(GraphEdit::SetName { id: id, name: name });
+				}
+				if master == Some(id) {
+					inverse.push(GraphEdit::SetMaster { id: master });
+				}
+				inverse.extend(edges.into_iter().map(|(edge_id, src, dest)| GraphEdit::AddEdge { id: Some(edge_id), src: src, dest: dest }));
+				AppliedEdit { created: None, inverse: inverse }
+			},
+			GraphEdit::ReplaceNode { id, node } => {
+				let node_ref = self.node_mut(id).expect("The checked node should exist");
+				let replaced = mem::replace(node_ref, node);
+				AppliedEdit { created: None, inverse: vec![GraphEdit::ReplaceNode { id: id, node: replaced }] }
+			},
+			GraphEdit::AddEdge { id, src, dest } => {
+				let cycle_error = |_| EditError::new(format!("Edge {} -> {} would create a cycle in the graph", src, dest));
+				match (id, self.find_edge(src, dest)) {
+					// Connecting already connected nodes has no effect
+					(None, Some(existing)) => AppliedEdit { created: Some(existing), inverse: vec![] },
+					(None, None) => {
+						let id = self.add_edge(src, dest).map_err(cycle_error)?;
+						AppliedEdit { created: Some(id), inverse: vec![GraphEdit::RemoveEdge { id: id }] }
+					},
+					(Some(id), _) => {
+						self.insert_edge(id, src, dest).map_err(cycle_error)?;
+						AppliedEdit { created: Some(id), inverse: vec![GraphEdit::RemoveEdge { id: id }] }
+					}
+				}
+			},
+			GraphEdit::RemoveEdge { id } => {
+				let (src, dest) = self.remove_edge(id).expect("The checked edge should exist");
+				AppliedEdit { created: None, inverse: vec![GraphEdit::AddEdge { id: Some(id), src: src, dest: dest }] }
+			},
+			GraphEdit::SetName { id, name } => {
+				let previous = self.set_name(id, name);
+				AppliedEdit { created: None, inverse: vec![GraphEdit::SetName { id: id, name: previous }] }
+			},
+			GraphEdit::SetMaster { id } => {
+				let previous = self.master();
+				self.set_master(id);
+				AppliedEdit { created: None, inverse: vec![GraphEdit::SetMaster { id: previous }] }
+			}
+		};
+		Ok(applied)
+	}
+	
+	/// Applies the edits in order, returning the edits
+	/// that revert all of them. If one of the edits fails,
+	/// the preceding ones are reverted and the error holds
+	/// the remaining edits along with those that were reverted.
+	pub fn apply_all<I>(&mut self, edits: I) -> Result<Vec<GraphEdit<N>>, EditError<N>> where I: IntoIterator<Item=GraphEdit<N>> {
+		let mut inverse = Vec::new();
+		let mut edits = edits.into_iter();
+		while let Some(edit) = edits.next() {
+			match self.apply(edit) {
+				Ok(applied) => prepend(&mut inverse, applied.inverse),
+				Err(mut e) => {
+					e.edits.extend(edits);
+					e.edits.extend(self.revert(inverse));
+					return Err(e);
+				}
+			}
+		}
+		Ok(inverse)
+	}
+	
+	/// Applies edits that were returned as the inverse
+	/// of successfully applied edits and thus can not
+	/// fail unless the graph was modified in between.
+	///
+	/// Returns the edits that revert the reverted ones,
+	/// which are handed back rather than dropped since
+	/// they may hold nodes.
+	pub fn revert(&mut self, inverse: Vec<GraphEdit<N>>) -> Vec<GraphEdit<N>> {
+		let mut reverted = Vec::new();
+		for edit in inverse {
+			match self.apply(edit) {
+				Ok(applied) => reverted.extend(applied.inverse),
+				Err(e) => {
+					error!("Could not revert graph edit: {}", e.message);
+					reverted.extend(e.edits);
+				}
+			}
+		}
+		reverted
+	}
+}
+
+impl<N> Retargetable for AudioGraph<N> where N: Node<StandardFrame> + Retargetable {
+	fn retarget(&mut self, target_sample_hz: f64) {
+		for &index in self.node_indices.values() {
+			if let Some(node) = self.graph.node_mut(index) {
+				node.retarget(target_sample_hz);
+			}
+		}
+	}
+}
+
+/// An edit that could not be applied.
+pub struct EditError<N> {
+	pub message: String,
+	/// The edits that have not been applied or have been
+	/// reverted. They are handed back (rather than dropped)
+	/// since they may hold nodes, which should not be
+	/// dropped on the render thread.
+	pub edits: Vec<GraphEdit<N>>
+}
+
+impl<N> EditError<N> {
+	pub fn new(message: impl Into<String>) -> EditError<N> {
+		EditError { message: message.into(), edits: Vec::new() }
+	}
+}
+
+/// A reversible modification of an audio graph.
+pub enum GraphEdit<N> {
+	/// Adds a node, optionally restoring the
+	/// identifier of a previously removed node
+	AddNode { id: Option<NodeId>, node: N },
+	/// Removes a node along with its edges
+	RemoveNode { id: NodeId },
+	ReplaceNode { id: NodeId, node: N },
+	/// Connects two nodes, optionally restoring the
+	/// identifier of a previously removed edge
+	AddEdge { id: Option<EdgeId>, src: NodeId, dest: NodeId },
+	RemoveEdge { id: EdgeId },
+	/// Names or (given `None`) unnames a node
+	SetName { id: NodeId, name: Option<Arc<str>> },
+	SetMaster { id: Option<NodeId> }
+}
+
+/// The outcome of applying a `GraphEdit`.
+pub struct AppliedEdit<N> {
+	/// The node or edge added by the edit
+	pub created: Option<usize>,
+	/// The edits that revert this edit
+	/// when applied in order
+	pub inverse: Vec<GraphEdit<N>>
+}
+
+/// Prepends the inverse of an edit to the inverse of
+/// the preceding edits, since they have to be reverted
+/// in the opposite order.
+pub fn prepend<N>(inverse: &mut Vec<GraphEdit<N>>, edit_inverse: Vec<GraphEdit<N>>) {
+	let preceding = mem::replace(inverse, edit_inverse);
+	inverse.extend(preceding);
+}
+
+/// A closure submitted by a control thread, which is
+/// run on the render thread and then handed back to
+/// the control thread along with its result.
+trait GraphTask<N>: Send {
+	fn run(&mut self, graph: &mut AudioGraph<N>);
+	
+	/// The control thread waiting for this task.
+	fn waiter(&self) -> &Thread;
+	
+	fn into_any(self: Box<Self>) -> Box<dyn Any>;
+}
+
+struct Task<F, R> {
+	f: Option<F>,
+	result: Option<R>,
+	waiter: Thread
+}
+
+impl<N, F, R> GraphTask<N> for Task<F, R> where F: FnOnce(&mut AudioGraph<N>) -> R + Send + 'static, R: Send + 'static {
+	fn run(&mut self, graph: &mut AudioGraph<N>) {
+		if let Some(f) = self.f.take() {
+			self.result = Some(f(graph));
+		}
+	}
+	
+	fn waiter(&self) -> &Thread { &self.waiter }
+	
+	fn into_any(self: Box<Self>) -> Box<dyn Any> { self }
+}
+
+/// The state shared between the control threads
+/// and the render thread.
+struct Handoff<N> {
+	/// Tasks waiting to be run by the render thread
+	pending: ArrayQueue<Box<dyn GraphTask<N>>>,
+	/// Tasks that have been run, waiting to be picked
+	/// up (and dropped) by the submitting thread
+	done: ArrayQueue<Box<dyn GraphTask<N>>>,
+	/// Serializes the control threads, thus at most
+	/// one task is in flight at any time
+	control: Mutex<()>,
+	/// Whether the renderer has been dropped, after
+	/// which the graph is parked here
+	detached: AtomicBool,
+	parked: Mutex<Option<AudioGraph<N>>>
+}
+
+/// The interval at which a waiting control thread
+/// checks whether the renderer has been dropped.
+const DETACH_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// How long a control thread waits for the render
+/// thread to pick up a closure, which should take
+/// no longer than an audio cycle.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A closure that the render thread did not pick up
+/// in time, e.g. since the engine is stuck on its
+/// output. The closure is handed back without
+/// having been run.
+pub struct GraphTimeout<F> {
+	pub message: String,
+	pub f: F
+}
+
+/// The control side of an audio graph that is owned
+/// (and rendered) by an engine's real-time thread.
+///
+/// Control threads (such as RPC-mechanisms) access the
+/// graph by submitting closures, which the render thread
+/// runs at the beginning of its next cycle. Thus the render
+/// thread never waits for a lock, while control threads
+/// wait for (at most) one cycle. Once the engine drops its
+/// renderer, closures are run on the calling thread.
+pub struct SharedAudioGraph<N> {
+	handoff: Arc<Handoff<N>>
+}
+
+impl<N> SharedAudioGraph<N> where N: Node<StandardFrame> + Send + 'static {
+	/// Runs a closure on the graph and returns its result.
+	///
+	/// The closure delays rendering and thus should be short.
+	/// In particular, nodes should be constructed beforehand
+	/// and values that are expensive to drop (such as removed
+	/// nodes) should be returned, since everything the closure
+	/// drops is dropped on the render thread.
+	///
+	/// Fails if the render thread does not pick up the
+	/// closure within `RESPONSE_TIMEOUT`.
+	pub fn with_graph<F, R>(&self, f: F) -> Result<R, GraphTimeout<F>> where F: FnOnce(&mut AudioGraph<N>) -> R + Send + 'static, R: Send + 'static {
+		let _control = self.handoff.control.lock();
+		if self.handoff.detached.load(Ordering::Acquire) {
+			return Ok(f(self.handoff.parked.lock().as_mut().expect("The detached graph should be parked")));
+		}
+		
+		let task: Box<dyn GraphTask<N>> = Box::new(Task { f: Some(f), result: None, waiter: thread::current() });
+		if self.handoff.pending.push(task).is_err() {
+			panic!("Only one graph task should be in flight");
+		}
+		
+		let deadline = Instant::now() + RESPONSE_TIMEOUT;
+		let task = loop {
+			if let Ok(task) = self.handoff.done.pop() {
+				break task;
+			}
+			if self.handoff.detached.load(Ordering::Acquire) {
+				// The renderer has been dropped, either
+				// before or after running the task
+				if let Ok(mut task) = self.handoff.pending.pop() {
+					task.run(self.handoff.parked.lock().as_mut().expect("The detached graph should be parked"));
+					break task;
+				}
+				continue;
+			}
+			if Instant::now() >= deadline {
+				// Unless the render thread is running the
+				// task right now, we take it back
+				if let Ok(task) = self.handoff.pending.pop() {
+					let task = task.into_any().downcast::<Task<F, R>>().expect("The taken back task should be the submitted one");
+					return Err(GraphTimeout {
+						message: "The engine did not respond in time".to_owned(),
+						f: task.f.expect("The taken back task should not have been run")
+					});
+				}
+			}
+			thread::park_timeout(DETACH_POLL_INTERVAL);
+		};
+		
+		let task = task.into_any().downcast::<Task<F, R>>().expect("The handed back task should be the submitted one");
+		Ok(task.result.expect("The handed back task should have been run"))
+	}
+	
+	/// Hands the graph to a new renderer after the previous
+	/// one has been dropped, e.g. to restart the engine.
+	/// Returns `None` while the previous renderer exists.
+	pub fn reattach(&self) -> Option<GraphRenderer<N>> {
+		let _control = self.handoff.control.lock();
+		if !self.handoff.detached.load(Ordering::Acquire) {
+			return None;
+		}
+		let graph = self.handoff.parked.lock().take()?;
+		self.handoff.detached.store(false, Ordering::Release);
+		Some(GraphRenderer { graph: graph, handoff: self.handoff.clone() })
+	}
+}
+
+impl<N> Clone for SharedAudioGraph<N> {
+	fn clone(&self) -> SharedAudioGraph<N> {
+		SharedAudioGraph { handoff: self.handoff.clone() }
+	}
+}
+
+/// The render side of a shared audio graph, which
+/// is owned by an engine.
+///
+/// Dropping the renderer (e.g. when the engine stops)
+/// hands the graph over to the control threads.
+pub struct GraphRenderer<N> where N: Node<StandardFrame> {
+	graph: AudioGraph<N>,
+	handoff: Arc<Handoff<N>>
+}
+
+impl<N> GraphRenderer<N> where N: Node<StandardFrame> {
+	/// Runs the closures submitted by control threads.
+	/// Engines call this at the beginning of every cycle,
+	/// including while paused, without blocking.
+	pub fn update(&mut self) {
+		while let Ok(mut task) = self.handoff.pending.pop() {
+			task.run(&mut self.graph);
+			let waiter = task.waiter().clone();
+			// Since at most one task is in flight, the
+			// queue of handed back tasks is never full
+			let _ = self.handoff.done.push(task);
+			waiter.unpark();
+		}
+	}
+	
+	/// Requests audio from the master node.
+	pub fn render(&mut self, buffer: &mut [StandardFrame], sample_hz: f64) {
+		self.graph.audio_requested(buffer, sample_hz);
+	}
+	
+	/// Adapts the nodes to a new output sample rate,
+	/// see `Retargetable`.
+	pub fn retarget(&mut self, target_sample_hz: f64) where N: Retargetable {
+		self.graph.retarget(target_sample_hz);
+	}
+	
+	pub fn graph(&self) -> &AudioGraph<N> { &self.graph }
+}
+
+impl<N> Drop for GraphRenderer<N> where N: Node<StandardFrame> {
+	fn drop(&mut self) {
+		let graph = mem::replace(&mut self.graph, AudioGraph::new());
+		*self.handoff.parked.lock() = Some(graph);
+		self.handoff.detached.store(true, Ordering::Release);
+	}
+}
+
+/// Creates a new shared audio graph with a single,
+/// empty master node, returning its control side
+/// (which can be cloned) and its render side.
+pub fn new_shared_graph<N>() -> (SharedAudioGraph<N>, GraphRenderer<N>) where N: Empty + Node<StandardFrame> {
 	let mut graph = AudioGraph::new();
 	let master = graph.add_node(N::empty());
 	graph.set_master(Some(master));
-	Arc::new(Mutex::new(graph))
+	let handoff = Arc::new(Handoff {
+		pending: ArrayQueue::new(1),
+		done: ArrayQueue::new(1),
+		control: Mutex::new(()),
+		detached: AtomicBool::new(false),
+		parked: Mutex::new(None)
+	});
+	(SharedAudioGraph { handoff: handoff.clone() }, GraphRenderer { graph: graph, handoff: handoff })
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_util::{TestNode, snapshot, graph_with_nodes};
+	
+	#[test]
+	fn removing_a_node_keeps_other_edges() {
+		let (mut graph, ids) = graph_with_nodes(4);
+		let (a, b, c, d) = (ids[0], ids[1], ids[2], ids[3]);
+		let ab = graph.add_edge(a, b).unwrap();
+		let cd = graph.add_edge(c, d).unwrap();
+		let bd = graph.add_edge(b, d).unwrap();
+		
+		// Moves the last node (d) into the first index
+		assert_eq!(graph.remove_node(a), Some(TestNode(0)));
+		assert_eq!(graph.node(d), Some(&TestNode(3)));
+		assert_eq!(graph.edges().collect::<Vec<_>>(), vec![(cd, c, d), (bd, b, d)]);
+		assert_eq!(graph.find_edge(a, b), None);
+		assert_eq!(graph.find_edge(c, d), Some(cd));
+		assert_eq!(graph.find_edge(b, d), Some(bd));
+		assert!(graph.remove_edge(ab).is_none());
+		
+		// The underlying connection follows the moved node
+		assert!(graph.add_edge(d, c).is_err());
+		assert_eq!(graph.remove_edge(cd), Some((c, d)));
+		assert!(graph.add_edge(d, c).is_ok());
+	}
+	
+	#[test]
+	fn removing_the_master_unsets_it() {
+		let (mut graph, ids) = graph_with_nodes(3);
+		graph.set_master(Some(ids[0]));
+		graph.remove_node(ids[0]);
+		assert_eq!(graph.master(), None);
+	}
+	
+	#[test]
+	fn master_follows_the_moved_node() {
+		let (mut graph, ids) = graph_with_nodes(3);
+		graph.set_master(Some(ids[2]));
+		// Moves the master into the first index
+		graph.remove_node(ids[0]);
+		assert_eq!(graph.master(), Some(ids[2]));
+		graph.remove_node(ids[1]);
+		assert_eq!(graph.master(), Some(ids[2]));
+		assert_eq!(graph.node(ids[2]), Some(&TestNode(2)));
+	}
+	
+	#[test]
+	fn removing_a_removed_edge_fails() {
+		let (mut graph, ids) = graph_with_nodes(3);
+		let edge = graph.add_edge(ids[0], ids[1]).unwrap();
+		graph.add_edge(ids[1], ids[2]).unwrap();
+		assert_eq!(graph.remove_edge(edge), Some((ids[0], ids[1])));
+		
+		let before = snapshot(&graph);
+		assert_eq!(graph.remove_edge(edge), None);
+		assert!(graph.apply(GraphEdit::RemoveEdge { id: edge }).is_err());
+		assert_eq!(snapshot(&graph), before);
+	}
+	
+	#[test]
+	fn node_ids_are_not_reused() {
+		let (mut graph, ids) = graph_with_nodes(2);
+		graph.remove_node(ids[1]);
+		let added = graph.add_node(TestNode(2));
+		assert!(!ids.contains(&added));
+		assert_eq!(graph.node(ids[1]), None);
+		assert_eq!(graph.node(added), Some(&TestNode(2)));
+	}
+	
+	#[test]
+	fn stale_node_ids_are_rejected() {
+		let (mut graph, ids) = graph_with_nodes(3);
+		let (a, stale) = (ids[0], ids[1]);
+		graph.add_edge(a, ids[2]).unwrap();
+		graph.set_master(Some(ids[2]));
+		graph.remove_node(stale);
+		let before = snapshot(&graph);
+		
+		assert!(graph.apply(GraphEdit::RemoveNode { id: stale }).is_err());
+		assert!(graph.apply(GraphEdit::AddEdge { id: None, src: a, dest: stale }).is_err());
+		assert!(graph.apply(GraphEdit::SetName { id: stale, name: Some("stale".into()) }).is_err());
+		assert!(graph.apply(GraphEdit::SetMaster { id: Some(stale) }).is_err());
+		match graph.apply(GraphEdit::ReplaceNode { id: stale, node: TestNode(9) }) {
+			// The rejected node is handed back
+			Err(e) => match e.edits.as_slice() {
+				[GraphEdit::ReplaceNode { node, .. }] => assert_eq!(*node, TestNode(9)),
+				_ => panic!("The rejected edit was not handed back")
+			},
+			Ok(..) => panic!("Replaced a removed node")
+		}
+		// Only removed identifiers may be restored
+		assert!(graph.apply(GraphEdit::AddNode { id: Some(a), node: TestNode(9) }).is_err());
+		assert!(graph.apply(GraphEdit::AddNode { id: Some(100), node: TestNode(9) }).is_err());
+		assert_eq!(snapshot(&graph), before);
+	}
+	
+	#[test]
+	fn failed_batches_are_rolled_back() {
+		let (mut graph, ids) = graph_with_nodes(3);
+		let edge = graph.add_edge(ids[0], ids[1]).unwrap();
+		graph.set_name(ids[0], Some("source".into()));
+		graph.set_master(Some(ids[1]));
+		let before = snapshot(&graph);
+		
+		let result = graph.apply_all(vec![
+			GraphEdit::AddNode { id: None, node: TestNode(3) },
+			GraphEdit::RemoveEdge { id: edge },
+			GraphEdit::SetName { id: ids[0], name: None },
+			GraphEdit::ReplaceNode { id: ids[2], node: TestNode(4) },
+			GraphEdit::RemoveNode { id: ids[1] },
+			// Fails since the node has just been removed
+			GraphEdit::AddEdge { id: None, src: ids[1], dest: ids[2] },
+			GraphEdit::SetMaster { id: None }
+		]);
+		match result {
+			// The failed and the remaining edit
+			// precede the reverted ones
+			Err(e) => assert!(e.edits.len() >= 2),
+			Ok(..) => panic!("Applied an edge to a removed node")
+		}
+		assert_eq!(snapshot(&graph), before);
+	}
+	
+	#[test]
+	fn batches_creating_cycles_are_rolled_back() {
+		let (mut graph, ids) = graph_with_nodes(3);
+		graph.add_edge(ids[0], ids[1]).unwrap();
+		let before = snapshot(&graph);
+		
+		let result = graph.apply_all(vec![
+			GraphEdit::AddEdge { id: None, src: ids[1], dest: ids[2] },
+			GraphEdit::AddEdge { id: None, src: ids[2], dest: ids[0] }
+		]);
+		assert!(result.is_err());
+		assert_eq!(snapshot(&graph), before);
+	}
 }
@@ -0,0 +1,167 @@
+use dsp::Node;
+use std::collections::VecDeque;
+use crate::audioformat::StandardFrame;
+use crate::graph::{AudioGraph, GraphEdit, EditError};
+
+/// A recorded edit, which stores the
+/// edits that revert it.
+pub struct HistoryEntry<N> {
+	pub description: String,
+	inverse: Vec<GraphEdit<N>>
+}
+
+impl<N> HistoryEntry<N> where N: Node<StandardFrame> {
+	/// Reverts the recorded edit, returning the
+	/// entry that reverts this reversion.
+	pub fn revert(self, graph: &mut AudioGraph<N>) -> Result<HistoryEntry<N>, EditError<N>> {
+		let HistoryEntry { description, inverse } = self;
+		let inverse = graph.apply_all(inverse)?;
+		Ok(HistoryEntry { description: description, inverse: inverse })
+	}
+}
+
+/// A bounded undo/redo history of graph edits.
+///
+/// Since the entries may hold removed or replaced
+/// nodes, entries that are discarded from the history
+/// are returned to the caller, which may then drop
+/// them outside of a lock.
+pub struct EditHistory<N> {
+	undo_stack: VecDeque<HistoryEntry<N>>,
+	redo_stack: Vec<HistoryEntry<N>>,
+	capacity: usize
+}
+
+impl<N> EditHistory<N> where N: Node<StandardFrame> {
+	/// Creates a history that keeps at
+	/// most `capacity` undoable edits.
+	pub fn new(capacity: usize) -> EditHistory<N> {
+		EditHistory {
+			undo_stack: VecDeque::new(),
+			redo_stack: Vec::new(),
+			capacity: capacity
+		}
+	}
+
+	/// Records an applied edit given its inverse, discarding
+	/// the redoable edits and (if the history is full) the
+	/// oldest undoable edit.
+	pub fn record(&mut self, description: impl Into<String>, inverse: Vec<GraphEdit<N>>) -> Vec<HistoryEntry<N>> {
+		if inverse.is_empty() {
+			// The edit had no effect
+			return Vec::new();
+		}
+		let mut discarded: Vec<_> = self.redo_stack.drain(..).collect();
+		self.undo_stack.push_back(HistoryEntry { description: description.into(), inverse: inverse });
+		while self.undo_stack.len() > self.capacity {
+			discarded.extend(self.undo_stack.pop_front());
+		}
+		discarded
+	}
+
+	/// Reverts the most recent edit, returning its description.
+	/// The entry is passed to `revert`, which reverts it on the
+	/// graph (e.g. `|entry| entry.revert(&mut graph)`). If the
+	/// edit can not be reverted, the graph is left unchanged and
+	/// the entry is discarded.
+	pub fn undo<F>(&mut self, revert: F) -> Result<String, String> where F: FnOnce(HistoryEntry<N>) -> Result<HistoryEntry<N>, EditError<N>> {
+		let entry = self.undo_stack.pop_back().ok_or_else(|| "There is nothing to undo".to_owned())?;
+		let description = entry.description.clone();
+		let redo = revert(entry).map_err(|e| format!("Could not undo '{}': {}", description, e.message))?;
+		self.redo_stack.push(redo);
+		Ok(description)
+	}
+
+	/// Reapplies the most recently undone edit, returning
+	/// its description. See `undo` regarding `revert`.
+	pub fn redo<F>(&mut self, revert: F) -> Result<String, String> where F: FnOnce(HistoryEntry<N>) -> Result<HistoryEntry<N>, EditError<N>> {
+		let entry = self.redo_stack.pop().ok_or_else(|| "There is nothing to redo".to_owned())?;
+		let description = entry.description.clone();
+		let undo = revert(entry).map_err(|e| format!("Could not redo '{}': {}", description, e.message))?;
+		self.undo_stack.push_back(undo);
+		Ok(description)
+	}
+
+	/// Discards all entries, returning them.
+	pub fn clear(&mut self) -> Vec<HistoryEntry<N>> {
+		let mut discarded: Vec<_> = self.undo_stack.drain(..).collect();
+		discarded.extend(self.redo_stack.drain(..));
+		discarded
+	}
+
+	/// Iterates the descriptions of the undoable
+	/// edits, starting with the most recent one.
+	pub fn undoable(&self) -> impl Iterator<Item=&str> + '_ {
+		self.undo_stack.iter().rev().map(|entry| entry.description.as_str())
+	}
+
+	/// Iterates the descriptions of the redoable
+	/// edits, starting with the most recent one.
+	pub fn redoable(&self) -> impl Iterator<Item=&str> + '_ {
+		self.redo_stack.iter().rev().map(|entry| entry.description.as_str())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_util::{TestNode, snapshot};
+	
+	/// Applies and records an edit.
+	fn edit(graph: &mut AudioGraph<TestNode>, history: &mut EditHistory<TestNode>, description: &str, edit: GraphEdit<TestNode>) {
+		let applied = graph.apply(edit).ok().expect("The edit should apply");
+		history.record(description, applied.inverse);
+	}
+	
+	#[test]
+	fn undo_and_redo_restore_the_graph() {
+		let mut graph = AudioGraph::new();
+		let mut history = EditHistory::new(10);
+		let source = graph.add_node(TestNode(0));
+		let filter = graph.add_node(TestNode(1));
+		let master = graph.add_node(TestNode(2));
+		graph.add_edge(source, filter).unwrap();
+		graph.add_edge(filter, master).unwrap();
+		graph.set_master(Some(master));
+		let initial = snapshot(&graph);
+		
+		edit(&mut graph, &mut history, "Name", GraphEdit::SetName { id: filter, name: Some("filter".into()) });
+		edit(&mut graph, &mut history, "Replace", GraphEdit::ReplaceNode { id: source, node: TestNode(3) });
+		edit(&mut graph, &mut history, "Remove", GraphEdit::RemoveNode { id: filter });
+		edit(&mut graph, &mut history, "Remove master", GraphEdit::RemoveNode { id: master });
+		let edited = snapshot(&graph);
+		assert_eq!(edited.0, vec![(source, 3)]);
+		
+		for description in &["Remove master", "Remove", "Replace", "Name"] {
+			assert_eq!(history.undo(|entry| entry.revert(&mut graph)), Ok(description.to_string()));
+		}
+		// The nodes and edges are restored with their identifiers
+		assert_eq!(snapshot(&graph), initial);
+		assert!(history.undo(|entry| entry.revert(&mut graph)).is_err());
+		
+		while history.redo(|entry| entry.revert(&mut graph)).is_ok() {}
+		assert_eq!(snapshot(&graph), edited);
+		while history.undo(|entry| entry.revert(&mut graph)).is_ok() {}
+		assert_eq!(snapshot(&graph), initial);
+	}
+	
+	#[test]
+	fn recording_discards_redoable_and_oldest_edits() {
+		let mut graph = AudioGraph::new();
+		let mut history = EditHistory::new(2);
+		let node = graph.add_node(TestNode(0));
+		for name in &["a", "b", "c"] {
+			edit(&mut graph, &mut history, name, GraphEdit::SetName { id: node, name: Some((*name).into()) });
+		}
+		assert_eq!(history.undoable().collect::<Vec<_>>(), vec!["c", "b"]);
+		
+		history.undo(|entry| entry.revert(&mut graph)).unwrap();
+		assert_eq!(graph.name(node), Some("b"));
+		assert_eq!(history.redoable().collect::<Vec<_>>(), vec!["c"]);
+		
+		let discarded = history.record("d", graph.apply(GraphEdit::SetName { id: node, name: None }).ok().unwrap().inverse);
+		assert_eq!(discarded.len(), 1);
+		assert_eq!(history.redoable().count(), 0);
+		assert_eq!(history.undoable().collect::<Vec<_>>(), vec!["d", "b"]);
+	}
+}
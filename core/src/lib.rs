@@ -2,7 +2,10 @@ pub mod engine;
 pub mod processing;
 pub mod source;
 pub mod graph;
+pub mod history;
 pub mod audioformat;
 pub mod util;
+#[cfg(test)]
+pub(crate) mod test_util;
 
 pub use dsp;
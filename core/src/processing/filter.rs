@@ -1,6 +1,7 @@
 //! A collection of lowpass and highpass filters
 
 use crate::audioformat::{StandardFrame, OpsExt};
+use crate::util::retargetable::Retargetable;
 use dsp::sample::Frame;
 use std::collections::VecDeque;
 use std::f32;
@@ -57,6 +58,12 @@ impl CutoffFreq for IIRLowpassFilter {
 	}
 }
 
+impl Retargetable for IIRLowpassFilter {
+	fn retarget(&mut self, target_sample_hz: f64) {
+		*self = IIRLowpassFilter::new(self.last_output, self.cutoff_hz, target_sample_hz);
+	}
+}
+
 impl Filter for IIRLowpassFilter {
 	type Frame = StandardFrame;
 
@@ -105,6 +112,12 @@ impl CutoffFreq for IIRHighpassFilter {
 	}
 }
 
+impl Retargetable for IIRHighpassFilter {
+	fn retarget(&mut self, target_sample_hz: f64) {
+		*self = IIRHighpassFilter::new(self.last_input, self.last_output, self.cutoff_hz, target_sample_hz);
+	}
+}
+
 impl Filter for IIRHighpassFilter {
 	type Frame = StandardFrame;
 
@@ -172,6 +185,10 @@ impl<L> Filter for Disableable<L> where L: Filter {
 	}
 }
 
+impl<L> Retargetable for Disableable<L> where L: Retargetable {
+	fn retarget(&mut self, target_sample_hz: f64) { self.wrapped.retarget(target_sample_hz) }
+}
+
 impl<L> Filter for Box<L> where L: Filter + ?Sized {
 	type Frame = L::Frame;
 
@@ -9,6 +9,8 @@ use filter::{Filter, MovingAverageFilter, IIRLowpassFilter, IIRHighpassFilter, D
 use crate::source::{AudioSource, file::FileSource, command::CommandSource, conv::Converting, pausable::Pausable};
 use crate::audioformat::StandardFrame;
 use crate::util::empty::Empty;
+use crate::util::exhaustible::Exhaustible;
+use crate::util::retargetable::Retargetable;
 
 /// An audio processing node which can either be a source
 /// or an intermediate node that performs some transformation
@@ -53,6 +55,46 @@ impl Empty for DspNode {
 	fn empty() -> Self { Self::Empty }
 }
 
+impl Exhaustible for DspNode {
+	fn is_source(&self) -> bool {
+		match *self {
+			Self::File(..) | Self::Command(..) | Self::DynSource(..) => true,
+			_ => false
+		}
+	}
+	
+	fn is_exhausted(&self) -> bool {
+		match *self {
+			Self::File(ref source) => source.is_exhausted(),
+			Self::Command(ref source) => source.is_exhausted(),
+			Self::DynSource(ref source) => source.is_exhausted(),
+			_ => true
+		}
+	}
+	
+	fn is_paused(&self) -> bool {
+		match *self {
+			Self::File(ref source) => source.paused,
+			Self::Command(ref source) => source.paused,
+			_ => false
+		}
+	}
+}
+
+impl Retargetable for DspNode {
+	fn retarget(&mut self, target_sample_hz: f64) {
+		match *self {
+			Self::File(ref mut source) => source.retarget(target_sample_hz),
+			Self::Command(ref mut source) => source.retarget(target_sample_hz),
+			Self::IIRLowpass(ref mut filter) => filter.retarget(target_sample_hz),
+			Self::IIRHighpass(ref mut filter) => filter.retarget(target_sample_hz),
+			// Dynamic nodes are opaque and the remaining
+			// ones do not depend on the sample rate
+			_ => ()
+		}
+	}
+}
+
 pub fn read_signal_into<S, F>(buffer: &mut [F], source: &mut S) where S: Signal<Frame=F>, F: Frame {
 	for i in 0..buffer.len() {
 		buffer[i] = source.next();
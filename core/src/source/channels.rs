@@ -0,0 +1,131 @@
+//! Mapping of arbitrary channel layouts
+//! onto the standard (stereo) frame.
+
+use crate::audioformat::{StandardFrame, StandardSample};
+use dsp::{Sample, FromSample};
+use std::f32;
+
+/// A loudspeaker position within a
+/// channel layout.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Speaker {
+	Mono,
+	Left,
+	Right,
+	Center,
+	LowFrequency,
+	SideLeft,
+	SideRight,
+	BackLeft,
+	BackRight,
+	BackCenter
+}
+
+impl Speaker {
+	/// The contribution of this speaker to
+	/// the left and right output channel.
+	fn gains(self) -> (f32, f32) {
+		let half = f32::consts::FRAC_1_SQRT_2;
+		match self {
+			Speaker::Mono => (1.0, 1.0),
+			Speaker::Left => (1.0, 0.0),
+			Speaker::Right => (0.0, 1.0),
+			Speaker::Center | Speaker::BackCenter => (half, half),
+			Speaker::LowFrequency => (0.0, 0.0),
+			Speaker::SideLeft | Speaker::BackLeft => (half, 0.0),
+			Speaker::SideRight | Speaker::BackRight => (0.0, half)
+		}
+	}
+}
+
+/// The channel orders used by RIFF WAV (and SMPTE),
+/// which MP3, FLAC and Opus share.
+fn wav_layout(channels: usize) -> Option<Vec<Speaker>> {
+	use Speaker::*;
+	match channels {
+		1 => Some(vec![Mono]),
+		2 => Some(vec![Left, Right]),
+		3 => Some(vec![Left, Right, Center]),
+		4 => Some(vec![Left, Right, SideLeft, SideRight]),
+		5 => Some(vec![Left, Right, Center, SideLeft, SideRight]),
+		6 => Some(vec![Left, Right, Center, LowFrequency, SideLeft, SideRight]),
+		7 => Some(vec![Left, Right, Center, LowFrequency, BackCenter, SideLeft, SideRight]),
+		8 => Some(vec![Left, Right, Center, LowFrequency, BackLeft, BackRight, SideLeft, SideRight]),
+		_ => None
+	}
+}
+
+/// The channel orders defined by the Vorbis specification.
+fn vorbis_layout(channels: usize) -> Option<Vec<Speaker>> {
+	use Speaker::*;
+	match channels {
+		1 => Some(vec![Mono]),
+		2 => Some(vec![Left, Right]),
+		3 => Some(vec![Left, Center, Right]),
+		4 => Some(vec![Left, Right, SideLeft, SideRight]),
+		5 => Some(vec![Left, Center, Right, SideLeft, SideRight]),
+		6 => Some(vec![Left, Center, Right, SideLeft, SideRight, LowFrequency]),
+		7 => Some(vec![Left, Center, Right, SideLeft, SideRight, BackCenter, LowFrequency]),
+		8 => Some(vec![Left, Center, Right, SideLeft, SideRight, BackLeft, BackRight, LowFrequency]),
+		_ => None
+	}
+}
+
+/// Unknown layouts are folded onto alternating
+/// left and right channels.
+fn fallback_layout(channels: usize) -> Vec<Speaker> {
+	(0..channels).map(|i| if i % 2 == 0 { Speaker::Left } else { Speaker::Right }).collect()
+}
+
+/// Upmixes mono and downmixes multichannel frames
+/// to a `StandardFrame`.
+#[derive(Debug, Clone)]
+pub struct ChannelMapper {
+	/// The (normalized) left and right gain per input channel
+	gains: Vec<(f32, f32)>
+}
+
+impl ChannelMapper {
+	/// Creates a mapper for the channel order
+	/// used by WAV, MP3, FLAC and Opus.
+	pub fn new(channels: usize) -> ChannelMapper {
+		ChannelMapper::from_layout(wav_layout(channels).unwrap_or_else(|| fallback_layout(channels)))
+	}
+
+	/// Creates a mapper for the channel order
+	/// used by Vorbis.
+	pub fn vorbis(channels: usize) -> ChannelMapper {
+		ChannelMapper::from_layout(vorbis_layout(channels).unwrap_or_else(|| fallback_layout(channels)))
+	}
+
+	fn from_layout(layout: Vec<Speaker>) -> ChannelMapper {
+		let gains: Vec<(f32, f32)> = layout.into_iter().map(Speaker::gains).collect();
+		// Normalize the gains to prevent the
+		// downmix from clipping
+		let left_sum: f32 = gains.iter().map(|g| g.0).sum();
+		let right_sum: f32 = gains.iter().map(|g| g.1).sum();
+		let left_norm = if left_sum > 1.0 { 1.0 / left_sum } else { 1.0 };
+		let right_norm = if right_sum > 1.0 { 1.0 / right_sum } else { 1.0 };
+		ChannelMapper { gains: gains.into_iter().map(|(l, r)| (l * left_norm, r * right_norm)).collect() }
+	}
+
+	/// The number of input channels.
+	pub fn channels(&self) -> usize { self.gains.len() }
+
+	/// Maps a single interleaved frame (containing
+	/// exactly `channels()` samples) to a standard frame.
+	pub fn map<S>(&self, samples: &[S]) -> StandardFrame where S: Sample, StandardSample: FromSample<S> {
+		match self.gains.len() {
+			// Fast paths for the common cases
+			1 => {
+				let sample = samples[0].to_sample();
+				[sample, sample]
+			},
+			2 => [samples[0].to_sample(), samples[1].to_sample()],
+			_ => samples.iter().zip(self.gains.iter()).fold([0.0, 0.0], |acc, (&sample, &(left, right))| {
+				let sample: StandardSample = sample.to_sample();
+				[acc[0] + sample * left, acc[1] + sample * right]
+			})
+		}
+	}
+}
@@ -1,5 +1,6 @@
 use std::process::{ChildStdout, ChildStdin, Command, Stdio};
 use std::io::Write;
+use std::sync::Arc;
 use byteorder::{BigEndian, ReadBytesExt};
 use dsp::{Signal, Frame};
 use crate::audioformat::StandardFrame;
@@ -11,8 +12,8 @@ use super::AudioSource;
 pub struct CommandSource {
 	child_out: ChildStdout,
 	child_in: Option<ChildStdin>,
-	command: String,
-	args: Vec<String>,
+	command: Arc<str>,
+	args: Arc<[String]>,
 	source_sample_hz: f64,
 	reached_end: bool,
 }
@@ -35,8 +36,8 @@ impl CommandSource {
 		Ok(CommandSource {
 			child_out: process.stdout.ok_or("Could not fetch child's stdout")?,
 			child_in: if takes_input { Some(process.stdin.ok_or("Could not fetch child's stdin")?) } else { None },
-			command: command.to_owned(),
-			args: args.iter().map(|s| (*s).to_owned()).collect(),
+			command: command.into(),
+			args: args.iter().map(|s| (*s).to_owned()).collect::<Vec<_>>().into(),
 			source_sample_hz: source_sample_hz,
 			reached_end: false
 		})
@@ -57,10 +58,10 @@ impl CommandSource {
 	pub fn input(&self) -> Option<&impl Write> { self.child_in.as_ref() }
 	
 	/// Fetches the executable that was invoked.
-	pub fn command(&self) -> &str { self.command.as_ref() }
+	pub fn command(&self) -> &Arc<str> { &self.command }
 	
 	/// Fetches the arguments from the command.
-	pub fn args(&self) -> &[String] { &self.args }
+	pub fn args(&self) -> &Arc<[String]> { &self.args }
 }
 
 impl AudioSource for CommandSource {
@@ -1,55 +1,126 @@
 use dsp::{Signal, Frame, DuplexSample};
-use dsp::sample::interpolate::{Converter, Linear};
-use crate::util::either::Either;
-use super::AudioSource;
+use dsp::sample::interpolate::Interpolator;
+use crate::util::retargetable::Retargetable;
+use super::{AudioSource, Seekable};
+use super::resample::{Resampler, ResampleQuality};
 
 /// An audio source that automatically converts to
 /// a target sample rate.
 pub struct Converting<S> where S: AudioSource, <S::Frame as Frame>::Sample: DuplexSample<f64> {
-	inner: Either<S, Converter<S, Linear<S::Frame>>>,
-	target_sample_hz: f64
+	wrapped: S,
+	/// The resampler, which is only needed if the
+	/// sample rates differ
+	resampling: Option<Resampling<S::Frame>>,
+	target_sample_hz: f64,
+	quality: ResampleQuality
+}
+
+/// The state of an ongoing sample rate conversion. Unlike
+/// dsp's `Converter`, it can be reset (e.g. after seeking)
+/// and retargeted.
+struct Resampling<F> {
+	resampler: Resampler<F>,
+	/// The position between the resampler's
+	/// current source frames
+	position: f64,
+	/// The number of source frames per output frame
+	ratio: f64
 }
 
 impl<S> Converting<S> where S: AudioSource, <S::Frame as Frame>::Sample: DuplexSample<f64> {
-	pub fn to_sample_hz(target_sample_hz: f64, mut wrapped: S) -> Converting<S> {
-		let source_sample_hz = wrapped.sample_hz();
-		Converting {
-			inner: if target_sample_hz == source_sample_hz {
-				// If the sample rates happen to match exactly
-				// despite being stored in floating points, we
-				// do not need a converter
-				Either::Left(wrapped)
-			} else {
-				let interpolator = Linear::from_source(&mut wrapped);
-				Either::Right(Converter::from_hz_to_hz(wrapped, interpolator, source_sample_hz, target_sample_hz))
-			},
-			target_sample_hz: target_sample_hz
-		}
+	pub fn to_sample_hz(target_sample_hz: f64, wrapped: S) -> Converting<S> {
+		Converting::with_quality(target_sample_hz, ResampleQuality::default(), wrapped)
 	}
 	
-	pub fn wrapped(&self) -> &S {
-		match self.inner {
-			Either::Left(ref wrapped) => wrapped,
-			Either::Right(ref converter) => converter.source()
-		}
+	pub fn with_quality(target_sample_hz: f64, quality: ResampleQuality, wrapped: S) -> Converting<S> {
+		let mut converting = Converting {
+			wrapped: wrapped,
+			resampling: None,
+			target_sample_hz: target_sample_hz,
+			quality: quality
+		};
+		converting.reset();
+		converting
 	}
 	
-	pub fn wrapped_mut(&mut self) -> &mut S {
-		match self.inner {
-			Either::Left(ref mut wrapped) => wrapped,
-			Either::Right(ref mut converter) => converter.source_mut()
+	/// Refills the resampler from the wrapped source.
+	fn reset(&mut self) {
+		let source_sample_hz = self.wrapped.sample_hz();
+		self.resampling = if self.target_sample_hz == source_sample_hz {
+			// If the sample rates happen to match exactly
+			// despite being stored in floating points, we
+			// do not need a converter
+			None
+		} else {
+			Some(Resampling {
+				resampler: Resampler::from_source(self.quality, &mut self.wrapped),
+				position: 0.0,
+				ratio: source_sample_hz / self.target_sample_hz
+			})
+		};
+	}
+	
+	pub fn quality(&self) -> ResampleQuality { self.quality }
+	
+	/// The time by which the wrapped source is ahead
+	/// of the output, since the resampler reads ahead.
+	pub fn latency_secs(&self) -> f64 {
+		match self.resampling {
+			Some(..) => self.quality.lookahead_frames() as f64 / self.wrapped.sample_hz(),
+			None => 0.0
 		}
 	}
+	
+	pub fn wrapped(&self) -> &S { &self.wrapped }
+	
+	pub fn wrapped_mut(&mut self) -> &mut S { &mut self.wrapped }
 }
 
 impl<S> AudioSource for Converting<S> where S: AudioSource, <S::Frame as Frame>::Sample: DuplexSample<f64> {
 	fn sample_hz(&self) -> f64 { self.target_sample_hz }
 }
 
+impl<S> Seekable for Converting<S> where S: AudioSource + Seekable, <S::Frame as Frame>::Sample: DuplexSample<f64> {
+	fn seek_secs(&mut self, secs: f64) -> Result<(), String> {
+		self.wrapped.seek_secs(secs)?;
+		// The resampler would otherwise blend the
+		// frames preceding the seek into the output
+		self.reset();
+		Ok(())
+	}
+}
+
+impl<S> Retargetable for Converting<S> where S: AudioSource, <S::Frame as Frame>::Sample: DuplexSample<f64> {
+	fn retarget(&mut self, target_sample_hz: f64) {
+		if target_sample_hz != self.target_sample_hz {
+			self.target_sample_hz = target_sample_hz;
+			self.reset();
+		}
+	}
+}
+
 impl<S> Signal for Converting<S> where S: AudioSource, <S::Frame as Frame>::Sample: DuplexSample<f64> {
 	type Frame = S::Frame;
 	
-	fn next(&mut self) -> S::Frame { self.inner.next() }
+	fn next(&mut self) -> S::Frame {
+		match self.resampling {
+			Some(ref mut resampling) => {
+				while resampling.position >= 1.0 {
+					resampling.resampler.next_source_frame(self.wrapped.next());
+					resampling.position -= 1.0;
+				}
+				let frame = resampling.resampler.interpolate(resampling.position);
+				resampling.position += resampling.ratio;
+				frame
+			},
+			None => self.wrapped.next()
+		}
+	}
 	
-	fn is_exhausted(&self) -> bool { self.inner.is_exhausted() }
+	fn is_exhausted(&self) -> bool {
+		match self.resampling {
+			Some(ref resampling) => self.wrapped.is_exhausted() && resampling.position >= 1.0,
+			None => self.wrapped.is_exhausted()
+		}
+	}
 }
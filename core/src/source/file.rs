@@ -1,30 +1,124 @@
-use std::io::BufReader;
+use std::fmt;
+use std::io::{BufRead, BufReader};
 use std::fs::File;
+use std::sync::Arc;
 use dsp::Signal;
 use crate::audioformat::StandardFrame;
 use super::mp3::Mp3Source;
-use super::AudioSource;
+use super::wav::WavSource;
+use super::flac::FlacSource;
+use super::ogg::{self, OggCodec, VorbisSource, OpusSource};
+use super::format::{FileFormat, UnrecognizedFormat};
+use super::mpeg;
+use super::metadata::TrackMetadata;
+use super::{AudioSource, Seekable};
+
+/// Indicates why a file could not be opened
+/// as an audio source.
+#[derive(Debug)]
+pub enum FileSourceError {
+	UnrecognizedFormat(UnrecognizedFormat),
+	/// The file could not be read or decoded
+	Other(String)
+}
+
+impl From<UnrecognizedFormat> for FileSourceError {
+	fn from(e: UnrecognizedFormat) -> FileSourceError { FileSourceError::UnrecognizedFormat(e) }
+}
+
+impl From<String> for FileSourceError {
+	fn from(e: String) -> FileSourceError { FileSourceError::Other(e) }
+}
+
+impl fmt::Display for FileSourceError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			FileSourceError::UnrecognizedFormat(ref e) => fmt::Display::fmt(e, f),
+			FileSourceError::Other(ref e) => write!(f, "{}", e)
+		}
+	}
+}
 
 /// An audio source whose format can automatically
-/// be determined from the file's path upon construction.
+/// be determined from the file upon construction.
 enum FileFormatSource {
-	Mp3(Mp3Source<BufReader<File>>)
-	// TODO: Other formats
+	Mp3(Mp3Source<BufReader<File>>),
+	Wav(WavSource<BufReader<File>>),
+	Flac(FlacSource<BufReader<File>>),
+	Vorbis(VorbisSource<BufReader<File>>),
+	Opus(OpusSource<BufReader<File>>)
 }
 
 impl FileFormatSource {
-	/// Tries to recognize the file's format and
+	/// Tries to recognize the file's format (by its
+	/// contents, falling back to the extension) and
 	/// create an appropriate decoder source for it.
-	/// Otherwise returns None.
-	fn from(file_path: &str) -> Result<FileFormatSource, String> {
-		let splittable_path = file_path.clone();
-		let splitter = splittable_path.split(".");
-		let reader = BufReader::new(File::open(file_path).map_err(|e| format!("{:?}", e))?);
-		let extension = splitter.last().ok_or("File has no extension")?;
-		match extension.as_ref() {
-			"mp3" => Ok(FileFormatSource::Mp3(Mp3Source::new(reader))),
-			// TODO: Other formats
-			_ => Err(format!("Unsupported file extension: {}", extension).to_owned())
+	fn from(file_path: &str) -> Result<FileFormatSource, FileSourceError> {
+		let mut reader = BufReader::new(File::open(file_path).map_err(|e| format!("{:?}", e))?);
+		// Peek at the leading bytes without consuming them
+		let format = {
+			let header = reader.fill_buf().map_err(|e| format!("{:?}", e))?;
+			FileFormat::detect(file_path, header)?
+		};
+		match format {
+			FileFormat::Mp3 => Ok(FileFormatSource::Mp3(Mp3Source::new(reader)?)),
+			FileFormat::Wav => Ok(FileFormatSource::Wav(WavSource::new(reader)?)),
+			FileFormat::Flac => Ok(FileFormatSource::Flac(FlacSource::new(reader)?)),
+			FileFormat::Ogg => match ogg::identify_codec(&mut reader)? {
+				OggCodec::Vorbis => Ok(FileFormatSource::Vorbis(VorbisSource::new(reader)?)),
+				OggCodec::Opus => Ok(FileFormatSource::Opus(OpusSource::new(reader)?))
+			}
+		}
+	}
+	
+	/// Fetches the detected format.
+	fn format(&self) -> FileFormat {
+		match *self {
+			FileFormatSource::Mp3(..) => FileFormat::Mp3,
+			FileFormatSource::Wav(..) => FileFormat::Wav,
+			FileFormatSource::Flac(..) => FileFormat::Flac,
+			FileFormatSource::Vorbis(..) | FileFormatSource::Opus(..) => FileFormat::Ogg
+		}
+	}
+	
+	/// Determines the length of the file in frames,
+	/// possibly by scanning it.
+	fn scan_duration(&self, file_path: &str) -> Option<u64> {
+		let open = || File::open(file_path).ok().map(BufReader::new);
+		match *self {
+			FileFormatSource::Mp3(..) => mpeg::duration_frames(&mut open()?),
+			FileFormatSource::Wav(ref src) => Some(src.duration_frames()),
+			FileFormatSource::Flac(ref src) => src.duration_frames(),
+			FileFormatSource::Vorbis(..) => ogg::last_granule_position(&mut open()?),
+			FileFormatSource::Opus(ref src) => ogg::last_granule_position(&mut open()?).map(|granule| granule.saturating_sub(src.header_pre_skip()))
+		}
+	}
+	
+	/// Fetches the error that caused decoding
+	/// to stop prematurely, if any.
+	fn error(&self) -> Option<&Arc<str>> {
+		match *self {
+			FileFormatSource::Mp3(ref src) => src.error(),
+			FileFormatSource::Wav(ref src) => src.error(),
+			FileFormatSource::Flac(ref src) => src.error(),
+			FileFormatSource::Vorbis(ref src) => src.error(),
+			FileFormatSource::Opus(ref src) => src.error()
+		}
+	}
+	
+	/// Skips the given number of frames, returning
+	/// the number of frames that were actually skipped.
+	fn skip(&mut self, frames: u64) -> u64 {
+		match *self {
+			FileFormatSource::Mp3(ref mut src) => src.skip(frames),
+			_ => {
+				let mut skipped = 0;
+				while skipped < frames && !self.is_exhausted() {
+					self.next();
+					skipped += 1;
+				}
+				skipped
+			}
 		}
 	}
 }
@@ -32,7 +126,11 @@ impl FileFormatSource {
 impl AudioSource for FileFormatSource {
 	fn sample_hz(&self) -> f64 {
 		match *self {
-			FileFormatSource::Mp3(ref src) => src.sample_hz()
+			FileFormatSource::Mp3(ref src) => src.sample_hz(),
+			FileFormatSource::Wav(ref src) => src.sample_hz(),
+			FileFormatSource::Flac(ref src) => src.sample_hz(),
+			FileFormatSource::Vorbis(ref src) => src.sample_hz(),
+			FileFormatSource::Opus(ref src) => src.sample_hz()
 		}
 	}
 }
@@ -42,13 +140,21 @@ impl Signal for FileFormatSource {
 	
 	fn next(&mut self) -> StandardFrame {
 		match *self {
-			FileFormatSource::Mp3(ref mut src) => src.next()
+			FileFormatSource::Mp3(ref mut src) => src.next(),
+			FileFormatSource::Wav(ref mut src) => src.next(),
+			FileFormatSource::Flac(ref mut src) => src.next(),
+			FileFormatSource::Vorbis(ref mut src) => src.next(),
+			FileFormatSource::Opus(ref mut src) => src.next()
 		}
 	}
 	
 	fn is_exhausted(&self) -> bool {
 		match *self {
-			FileFormatSource::Mp3(ref src) => src.is_exhausted()
+			FileFormatSource::Mp3(ref src) => src.is_exhausted(),
+			FileFormatSource::Wav(ref src) => src.is_exhausted(),
+			FileFormatSource::Flac(ref src) => src.is_exhausted(),
+			FileFormatSource::Vorbis(ref src) => src.is_exhausted(),
+			FileFormatSource::Opus(ref src) => src.is_exhausted()
 		}
 	}
 }
@@ -57,28 +163,83 @@ impl Signal for FileFormatSource {
 /// a path to the file it was created from.
 pub struct FileSource {
 	wrapped: FileFormatSource,
-	file_path: String
+	file_path: Arc<str>,
+	/// The number of frames read so far
+	position: u64,
+	/// The length of the file in frames, if known
+	duration: Option<u64>,
+	metadata: Arc<TrackMetadata>
 }
 
 impl FileSource {
-	pub fn new(file_path: &str) -> Result<FileSource, String> {
+	pub fn new(file_path: &str) -> Result<FileSource, FileSourceError> {
+		let wrapped = FileFormatSource::from(file_path)?;
+		let duration = wrapped.scan_duration(file_path);
+		let metadata = TrackMetadata::read(file_path, wrapped.format());
 		Ok(FileSource {
-			wrapped: FileFormatSource::from(file_path)?,
-			file_path: file_path.to_owned()
+			wrapped: wrapped,
+			file_path: file_path.into(),
+			position: 0,
+			duration: duration,
+			metadata: Arc::new(metadata)
 		})
 	}
 	
-	pub fn file_path(&self) -> &str { self.file_path.as_ref() }
+	/// Fetches the path, which is shared to
+	/// make copies cheap.
+	pub fn file_path(&self) -> &Arc<str> { &self.file_path }
+	
+	/// Fetches the current playback position in seconds.
+	pub fn position_secs(&self) -> f64 { self.position as f64 / self.sample_hz() }
+	
+	/// Fetches the length of the file in seconds if the
+	/// format permits determining it.
+	pub fn duration_secs(&self) -> Option<f64> { self.duration.map(|duration| duration as f64 / self.sample_hz()) }
+	
+	/// Fetches the error that caused decoding to
+	/// stop prematurely, if any. The source is
+	/// exhausted in this case.
+	pub fn error(&self) -> Option<&Arc<str>> { self.wrapped.error() }
+	
+	/// Fetches the tags and cover art read from the file,
+	/// which are shared to make copies cheap.
+	pub fn metadata(&self) -> &Arc<TrackMetadata> { &self.metadata }
 }
 
 impl AudioSource for FileSource {
 	fn sample_hz(&self) -> f64 { self.wrapped.sample_hz() }
 }
 
+impl Seekable for FileSource {
+	/// Seeks to a frame-accurate position. Formats
+	/// without native seeking support are decoded
+	/// (and reopened when seeking backwards).
+	fn seek_secs(&mut self, secs: f64) -> Result<(), String> {
+		let target = (secs.max(0.0) * self.sample_hz()).round() as u64;
+		if let FileFormatSource::Wav(ref mut src) = self.wrapped {
+			self.position = src.seek(target)?;
+			return Ok(());
+		}
+		if target < self.position {
+			self.wrapped = FileFormatSource::from(&self.file_path).map_err(|e| e.to_string())?;
+			self.position = 0;
+		}
+		self.position += self.wrapped.skip(target - self.position);
+		Ok(())
+	}
+}
+
 impl Signal for FileSource {
 	type Frame = StandardFrame;
 	
-	fn next(&mut self) -> StandardFrame { self.wrapped.next() }
+	fn next(&mut self) -> StandardFrame {
+		// The last frame is read before the source is
+		// exhausted, thus we need to check beforehand
+		if !self.wrapped.is_exhausted() {
+			self.position += 1;
+		}
+		self.wrapped.next()
+	}
 	
 	fn is_exhausted(&self) -> bool { self.wrapped.is_exhausted() }
 }
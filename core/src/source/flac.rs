@@ -0,0 +1,115 @@
+use super::AudioSource;
+use super::channels::ChannelMapper;
+use crate::audioformat::{StandardFrame, StandardSample};
+use std::io::Read;
+use std::mem;
+use std::sync::Arc;
+use dsp::{Frame, Signal};
+use claxon::{FlacReader, Block};
+
+/// A FLAC decoder supporting bit depths up to
+/// 24 bits and arbitrary block sizes.
+pub struct FlacSource<R> where R: Read {
+	reader: FlacReader<R>,
+	sample_rate: f64,
+	duration: Option<u64>,
+	/// The factor that normalizes samples to [-1.0, 1.0]
+	int_scale: StandardSample,
+	eof: bool,
+	error: Option<Arc<str>>,
+	block: Block,
+	block_offset: u32,
+	channel_mapper: ChannelMapper,
+	flac_frame_data: Vec<StandardSample>
+}
+
+impl<R> FlacSource<R> where R: Read {
+	pub fn new(reader: R) -> Result<FlacSource<R>, String> {
+		let reader = FlacReader::new(reader).map_err(|e| format!("Could not read FLAC metadata: {}", e))?;
+		let streaminfo = reader.streaminfo();
+
+		if streaminfo.bits_per_sample > 24 {
+			return Err(format!("FLAC has a bit depth of {}, while at most 24 bits are supported", streaminfo.bits_per_sample));
+		}
+
+		Ok(FlacSource {
+			reader: reader,
+			sample_rate: streaminfo.sample_rate as f64,
+			duration: streaminfo.samples,
+			int_scale: 1.0 / ((1u32 << (streaminfo.bits_per_sample - 1)) as StandardSample),
+			eof: false,
+			error: None,
+			block: Block::empty(),
+			block_offset: 0,
+			channel_mapper: ChannelMapper::new(streaminfo.channels as usize),
+			flac_frame_data: Vec::with_capacity(streaminfo.channels as usize)
+		})
+	}
+
+	/// The length of the stream in frames,
+	/// if stored in the header.
+	pub fn duration_frames(&self) -> Option<u64> { self.duration }
+
+	/// Fetches the error that caused decoding
+	/// to stop prematurely, if any.
+	pub fn error(&self) -> Option<&Arc<str>> { self.error.as_ref() }
+
+	/// Decodes the next block, reusing the buffer
+	/// of the current one. Returns false if the end
+	/// of the stream was reached.
+	fn read_block(&mut self) -> bool {
+		let buffer = mem::replace(&mut self.block, Block::empty()).into_buffer();
+		match self.reader.blocks().read_next_or_eof(buffer) {
+			Ok(Some(block)) => {
+				self.block = block;
+				self.block_offset = 0;
+				true
+			},
+			Ok(None) => false,
+			Err(e) => {
+				// FLAC frames can not be resynchronized
+				// reliably, thus we stop decoding
+				self.error = Some(format!("Could not decode FLAC block: {}", e).into());
+				false
+			}
+		}
+	}
+}
+
+impl<R> AudioSource for FlacSource<R> where R: Read {
+	fn sample_hz(&self) -> f64 { self.sample_rate }
+}
+
+impl<R> Signal for FlacSource<R> where R: Read {
+	type Frame = StandardFrame;
+
+	fn next(&mut self) -> StandardFrame {
+		if self.eof {
+			return StandardFrame::equilibrium();
+		}
+
+		// Blocks may be empty, thus we loop until
+		// we find one containing samples
+		while self.block_offset >= self.block.duration() {
+			if !self.read_block() {
+				self.eof = true;
+				return StandardFrame::equilibrium();
+			}
+		}
+
+		let channels = self.block.channels();
+		if channels as usize != self.channel_mapper.channels() {
+			self.channel_mapper = ChannelMapper::new(channels as usize);
+		}
+
+		self.flac_frame_data.clear();
+		for channel in 0..channels {
+			self.flac_frame_data.push(self.block.sample(channel, self.block_offset) as StandardSample * self.int_scale);
+		}
+
+		self.block_offset += 1;
+		self.channel_mapper.map(&self.flac_frame_data)
+	}
+
+	fn is_exhausted(&self) -> bool { self.eof }
+}
@@ -0,0 +1,99 @@
+//! Detection of audio file formats from their
+//! contents and paths.
+
+use std::fmt;
+use std::path::Path;
+
+/// A container/file format supported by
+/// `FileSource`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FileFormat {
+	Mp3,
+	Wav,
+	Flac,
+	Ogg
+}
+
+/// The formats whose signatures are checked,
+/// in the order they are probed. MPEG frame syncs
+/// are the weakest signature, thus MP3 comes last.
+pub const PROBED_FORMATS: [FileFormat; 4] = [FileFormat::Wav, FileFormat::Flac, FileFormat::Ogg, FileFormat::Mp3];
+
+impl FileFormat {
+	/// Checks whether the given leading bytes of
+	/// a file match this format's signature.
+	pub fn matches(self, header: &[u8]) -> bool {
+		match self {
+			FileFormat::Wav => header.len() >= 12 && &header[0..4] == b"RIFF" && &header[8..12] == b"WAVE",
+			FileFormat::Flac => header.starts_with(b"fLaC"),
+			FileFormat::Ogg => header.starts_with(b"OggS"),
+			// Either an ID3v2 tag or an MPEG audio frame sync
+			FileFormat::Mp3 => header.starts_with(b"ID3") || (header.len() >= 2 && header[0] == 0xFF && (header[1] & 0xE0) == 0xE0)
+		}
+	}
+
+	/// Recognizes the format from the leading
+	/// bytes of a file.
+	pub fn probe(header: &[u8]) -> Option<FileFormat> {
+		PROBED_FORMATS.iter().cloned().find(|format| format.matches(header))
+	}
+
+	/// Recognizes the format from a (case-insensitive)
+	/// file extension.
+	pub fn from_extension(extension: &str) -> Option<FileFormat> {
+		match extension.to_lowercase().as_ref() {
+			"mp3" => Some(FileFormat::Mp3),
+			"wav" | "wave" => Some(FileFormat::Wav),
+			"flac" => Some(FileFormat::Flac),
+			"ogg" | "oga" | "opus" => Some(FileFormat::Ogg),
+			_ => None
+		}
+	}
+
+	/// Recognizes the format from the leading bytes
+	/// of a file, falling back to the path's extension
+	/// if no signature matches.
+	pub fn detect(file_path: &str, header: &[u8]) -> Result<FileFormat, UnrecognizedFormat> {
+		let extension = Path::new(file_path).extension().and_then(|e| e.to_str());
+		FileFormat::probe(header)
+			.or_else(|| extension.and_then(FileFormat::from_extension))
+			.ok_or_else(|| UnrecognizedFormat {
+				file_path: file_path.to_owned(),
+				probed: PROBED_FORMATS.to_vec(),
+				extension: extension.map(|e| e.to_owned())
+			})
+	}
+}
+
+impl fmt::Display for FileFormat {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			FileFormat::Mp3 => write!(f, "MP3 (ID3/MPEG sync)"),
+			FileFormat::Wav => write!(f, "WAV (RIFF)"),
+			FileFormat::Flac => write!(f, "FLAC (fLaC)"),
+			FileFormat::Ogg => write!(f, "Ogg (OggS)")
+		}
+	}
+}
+
+/// Indicates that neither the contents nor
+/// the extension of a file matched a supported format.
+#[derive(Debug)]
+pub struct UnrecognizedFormat {
+	pub file_path: String,
+	/// The formats whose signatures were checked
+	pub probed: Vec<FileFormat>,
+	/// The file extension used as a fallback, if any
+	pub extension: Option<String>
+}
+
+impl fmt::Display for UnrecognizedFormat {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let probed: Vec<String> = self.probed.iter().map(|format| format.to_string()).collect();
+		write!(f, "Could not recognize the format of {}, probed: {}", self.file_path, probed.join(", "))?;
+		match self.extension {
+			Some(ref extension) => write!(f, "; unsupported file extension: {}", extension),
+			None => write!(f, "; file has no extension")
+		}
+	}
+}
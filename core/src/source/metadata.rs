@@ -0,0 +1,492 @@
+//! Extraction of track metadata (tags and cover art)
+//! from audio files.
+
+use std::io::{self, Read, Seek, SeekFrom, BufReader};
+use std::fs::File;
+use byteorder::{BigEndian, LittleEndian, ByteOrder};
+use log::warn;
+use super::format::FileFormat;
+
+/// Descriptive metadata about a track, as stored in
+/// ID3 tags, Vorbis comments or RIFF INFO chunks.
+#[derive(Debug, Clone, Default)]
+pub struct TrackMetadata {
+	pub title: Option<String>,
+	pub artist: Option<String>,
+	pub album: Option<String>,
+	pub album_artist: Option<String>,
+	pub track_number: Option<String>,
+	pub date: Option<String>,
+	pub genre: Option<String>,
+	pub cover_art: Option<CoverArt>
+}
+
+/// An embedded picture, usually the front cover.
+#[derive(Debug, Clone)]
+pub struct CoverArt {
+	pub mime_type: String,
+	pub description: String,
+	pub data: Vec<u8>
+}
+
+/// The picture type of a front cover in both
+/// ID3 and FLAC pictures.
+const FRONT_COVER: u8 = 3;
+
+/// The maximum size of a tag, block or chunk that is
+/// read into memory, which leaves room for large covers.
+const MAX_TAG_LEN: u64 = 64 * 1024 * 1024;
+
+impl TrackMetadata {
+	/// Reads the metadata of a file in the given format.
+	/// Metadata is best-effort, thus missing or malformed
+	/// tags result in empty fields rather than an error.
+	pub fn read(file_path: &str, format: FileFormat) -> TrackMetadata {
+		let mut reader = match File::open(file_path) {
+			Ok(file) => BufReader::new(file),
+			Err(e) => {
+				warn!("Could not open {} to read metadata: {:?}", file_path, e);
+				return TrackMetadata::default();
+			}
+		};
+		let metadata = match format {
+			// ID3v2 tags take precedence over ID3v1 tags
+			FileFormat::Mp3 => Some(read_id3v2(&mut reader).unwrap_or_default().or(read_id3v1(&mut reader).unwrap_or_default())),
+			FileFormat::Wav => read_riff(&mut reader),
+			FileFormat::Flac => read_flac(&mut reader),
+			FileFormat::Ogg => read_ogg(&mut reader)
+		};
+		metadata.unwrap_or_default()
+	}
+
+	/// Fills missing fields from another
+	/// (lower-priority) set of metadata.
+	pub fn or(self, other: TrackMetadata) -> TrackMetadata {
+		TrackMetadata {
+			title: self.title.or(other.title),
+			artist: self.artist.or(other.artist),
+			album: self.album.or(other.album),
+			album_artist: self.album_artist.or(other.album_artist),
+			track_number: self.track_number.or(other.track_number),
+			date: self.date.or(other.date),
+			genre: self.genre.or(other.genre),
+			cover_art: self.cover_art.or(other.cover_art)
+		}
+	}
+
+	/// Sets a cover, preferring front covers over
+	/// other picture types.
+	fn offer_cover_art(&mut self, picture_type: u8, cover_art: CoverArt) {
+		if self.cover_art.is_none() || picture_type == FRONT_COVER {
+			self.cover_art = Some(cover_art);
+		}
+	}
+}
+
+/// Reads a slice of the given length at the
+/// position, advancing it.
+fn take<'a>(data: &'a [u8], pos: &mut usize, len: usize) -> Option<&'a [u8]> {
+	let end = pos.checked_add(len)?;
+	let slice = data.get(*pos..end)?;
+	*pos = end;
+	Some(slice)
+}
+
+/// Reads data whose length is taken from a (possibly
+/// corrupt) header. Data exceeding the remainder of the
+/// file or `MAX_TAG_LEN` is skipped without allocating,
+/// yielding `None`.
+fn read_or_skip<R>(reader: &mut R, len: u64) -> io::Result<Option<Vec<u8>>> where R: Read + Seek {
+	let pos = reader.seek(SeekFrom::Current(0))?;
+	let end = reader.seek(SeekFrom::End(0))?;
+	if len > MAX_TAG_LEN || len > end.saturating_sub(pos) {
+		warn!("Skipping metadata of {} bytes at offset {}", len, pos);
+		reader.seek(SeekFrom::Start(pos.saturating_add(len)))?;
+		return Ok(None);
+	}
+	reader.seek(SeekFrom::Start(pos))?;
+	let mut data = vec![0u8; len as usize];
+	reader.read_exact(&mut data)?;
+	Ok(Some(data))
+}
+
+/// Decodes ISO-8859-1 text.
+fn decode_latin1(bytes: &[u8]) -> String {
+	bytes.iter().map(|&b| b as char).collect()
+}
+
+/// Removes padding and whitespace from a
+/// (possibly empty) field.
+fn non_empty(text: String) -> Option<String> {
+	let trimmed = text.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+	if trimmed.is_empty() { None } else { Some(trimmed.to_owned()) }
+}
+
+// ID3
+
+/// Decodes an ID3v1 tag from the last 128 bytes of the file.
+fn read_id3v1<R>(reader: &mut R) -> Option<TrackMetadata> where R: Read + Seek {
+	let mut tag = [0u8; 128];
+	reader.seek(SeekFrom::End(-128)).ok()?;
+	reader.read_exact(&mut tag).ok()?;
+	if &tag[0..3] != b"TAG" {
+		return None;
+	}
+	let field = |range: std::ops::Range<usize>| {
+		let bytes = &tag[range];
+		let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+		non_empty(decode_latin1(&bytes[..end]))
+	};
+	Some(TrackMetadata {
+		title: field(3..33),
+		artist: field(33..63),
+		album: field(63..93),
+		date: field(93..97),
+		// ID3v1.1 stores the track number in the last comment byte
+		track_number: if tag[125] == 0 && tag[126] != 0 { Some(tag[126].to_string()) } else { None },
+		..TrackMetadata::default()
+	})
+}
+
+/// Decodes a 'syncsafe' integer whose bytes
+/// only use the lower 7 bits.
+fn syncsafe(bytes: &[u8]) -> usize {
+	bytes.iter().fold(0, |acc, &b| (acc << 7) | (b & 0x7F) as usize)
+}
+
+/// Reverses the ID3 unsynchronisation scheme
+/// which inserts zeros after 0xFF bytes.
+fn remove_unsync(data: &[u8]) -> Vec<u8> {
+	let mut result = Vec::with_capacity(data.len());
+	for (i, &b) in data.iter().enumerate() {
+		if b != 0 || i == 0 || data[i - 1] != 0xFF {
+			result.push(b);
+		}
+	}
+	result
+}
+
+/// Decodes ID3v2 text in the given encoding,
+/// joining multiple null-separated values.
+fn decode_id3_text(encoding: u8, bytes: &[u8]) -> String {
+	let text = match encoding {
+		0 => decode_latin1(bytes),
+		1 | 2 => {
+			// UTF-16 text may start with a byte order mark
+			let (big_endian, bytes) = if bytes.starts_with(&[0xFF, 0xFE]) {
+				(false, &bytes[2..])
+			} else if bytes.starts_with(&[0xFE, 0xFF]) {
+				(true, &bytes[2..])
+			} else {
+				(encoding == 2, bytes)
+			};
+			let units: Vec<u16> = bytes.chunks_exact(2)
+				.map(|c| if big_endian { BigEndian::read_u16(c) } else { LittleEndian::read_u16(c) })
+				.collect();
+			String::from_utf16_lossy(&units)
+		},
+		_ => String::from_utf8_lossy(bytes).into_owned()
+	};
+	text.split('\0').filter(|s| !s.is_empty()).collect::<Vec<_>>().join("; ")
+}
+
+/// Splits null-terminated text in the given encoding
+/// off the beginning of the data.
+fn split_id3_terminated(encoding: u8, data: &[u8]) -> (&[u8], &[u8]) {
+	let wide = encoding == 1 || encoding == 2;
+	let end = if wide {
+		(0..data.len() / 2).map(|i| i * 2).find(|&i| data[i] == 0 && data[i + 1] == 0)
+	} else {
+		data.iter().position(|&b| b == 0)
+	};
+	match end {
+		Some(end) => (&data[..end], &data[end + if wide { 2 } else { 1 }..]),
+		None => (data, &data[data.len()..])
+	}
+}
+
+/// Decodes an attached picture frame (APIC or
+/// PIC in ID3v2.2), returning its type and contents.
+fn decode_id3_picture(major: u8, data: &[u8]) -> Option<(u8, CoverArt)> {
+	let mut pos = 0;
+	let encoding = take(data, &mut pos, 1)?[0];
+	let mime_type = if major == 2 {
+		// ID3v2.2 uses a three-character image format
+		match take(data, &mut pos, 3)? {
+			b"PNG" => "image/png".to_owned(),
+			b"JPG" => "image/jpeg".to_owned(),
+			format => format!("image/{}", decode_latin1(format).to_lowercase())
+		}
+	} else {
+		let (mime_type, _) = split_id3_terminated(0, &data[pos..]);
+		pos += mime_type.len() + 1;
+		decode_latin1(mime_type)
+	};
+	let picture_type = take(data, &mut pos, 1)?[0];
+	let (description, picture) = split_id3_terminated(encoding, data.get(pos..)?);
+	Some((picture_type, CoverArt {
+		mime_type: mime_type,
+		description: decode_id3_text(encoding, description),
+		data: picture.to_vec()
+	}))
+}
+
+/// Decodes an ID3v2.2, 2.3 or 2.4 tag at the
+/// beginning of the given reader.
+fn read_id3v2<R>(reader: &mut R) -> Option<TrackMetadata> where R: Read + Seek {
+	let mut header = [0u8; 10];
+	reader.read_exact(&mut header).ok()?;
+	if &header[0..3] != b"ID3" {
+		return None;
+	}
+	let tag = read_or_skip(reader, syncsafe(&header[6..10]) as u64).ok()??;
+	Some(decode_id3v2(header[3], header[5], tag))
+}
+
+fn decode_id3v2(major: u8, flags: u8, mut tag: Vec<u8>) -> TrackMetadata {
+	let mut metadata = TrackMetadata::default();
+	if flags & 0x80 != 0 && major < 4 {
+		tag = remove_unsync(&tag);
+	}
+
+	let mut pos = 0;
+	if flags & 0x40 != 0 {
+		// Skip the extended header
+		pos = match (major, tag.get(0..4)) {
+			(3, Some(size)) => 4 + BigEndian::read_u32(size) as usize,
+			(4, Some(size)) => syncsafe(size),
+			_ => 0
+		};
+	}
+
+	let header_len = if major == 2 { 6 } else { 10 };
+	while pos + header_len <= tag.len() && tag[pos] != 0 {
+		let header = &tag[pos..pos + header_len];
+		let (id, size, format_flags) = match major {
+			2 => (&header[0..3], BigEndian::read_uint(&header[3..6], 3) as usize, 0),
+			3 => (&header[0..4], BigEndian::read_u32(&header[4..8]) as usize, header[9]),
+			_ => (&header[0..4], syncsafe(&header[4..8]), header[9])
+		};
+		pos += header_len;
+		let mut data = match tag.get(pos..pos + size) {
+			Some(data) => data.to_vec(),
+			None => break
+		};
+		pos += size;
+
+		// Skip compressed and encrypted frames
+		if (major == 3 && format_flags & 0xC0 != 0) || (major == 4 && format_flags & 0x0C != 0) {
+			continue;
+		}
+		if major == 4 && format_flags & 0x02 != 0 {
+			data = remove_unsync(&data);
+		}
+		if major == 4 && format_flags & 0x01 != 0 && data.len() >= 4 {
+			// Skip the data length indicator
+			data.drain(0..4);
+		}
+		if data.is_empty() {
+			continue;
+		}
+
+		let text = || non_empty(decode_id3_text(data[0], &data[1..]));
+		match id {
+			b"TIT2" | b"TT2" => metadata.title = text(),
+			b"TPE1" | b"TP1" => metadata.artist = text(),
+			b"TALB" | b"TAL" => metadata.album = text(),
+			b"TPE2" | b"TP2" => metadata.album_artist = text(),
+			b"TRCK" | b"TRK" => metadata.track_number = text(),
+			b"TDRC" | b"TYER" | b"TYE" => metadata.date = text(),
+			b"TCON" | b"TCO" => metadata.genre = text(),
+			b"APIC" | b"PIC" => if let Some((picture_type, cover_art)) = decode_id3_picture(major, &data) {
+				metadata.offer_cover_art(picture_type, cover_art);
+			},
+			_ => {}
+		}
+	}
+
+	metadata
+}
+
+// Vorbis comments
+
+/// Decodes a FLAC picture (used both as a metadata
+/// block and as a base64-encoded Vorbis comment).
+fn decode_flac_picture(data: &[u8]) -> Option<(u8, CoverArt)> {
+	let mut pos = 0;
+	let picture_type = BigEndian::read_u32(take(data, &mut pos, 4)?);
+	let mime_len = BigEndian::read_u32(take(data, &mut pos, 4)?) as usize;
+	let mime_type = decode_latin1(take(data, &mut pos, mime_len)?);
+	let description_len = BigEndian::read_u32(take(data, &mut pos, 4)?) as usize;
+	let description = String::from_utf8_lossy(take(data, &mut pos, description_len)?).into_owned();
+	// Skip width, height, color depth and palette size
+	take(data, &mut pos, 16)?;
+	let data_len = BigEndian::read_u32(take(data, &mut pos, 4)?) as usize;
+	let picture = take(data, &mut pos, data_len)?;
+	Some((picture_type as u8, CoverArt {
+		mime_type: mime_type,
+		description: description,
+		data: picture.to_vec()
+	}))
+}
+
+/// Decodes a Vorbis comment header (without
+/// the codec-specific prefix).
+fn decode_vorbis_comments(data: &[u8]) -> Option<TrackMetadata> {
+	let mut metadata = TrackMetadata::default();
+	let mut pos = 0;
+	let vendor_len = LittleEndian::read_u32(take(data, &mut pos, 4)?) as usize;
+	take(data, &mut pos, vendor_len)?;
+	let count = LittleEndian::read_u32(take(data, &mut pos, 4)?);
+
+	for _ in 0..count {
+		let len = LittleEndian::read_u32(take(data, &mut pos, 4)?) as usize;
+		let comment = String::from_utf8_lossy(take(data, &mut pos, len)?).into_owned();
+		let mut split = comment.splitn(2, '=');
+		let key = split.next().unwrap_or("").to_uppercase();
+		let value = split.next().and_then(|v| non_empty(v.to_owned()));
+		match key.as_ref() {
+			"TITLE" => metadata.title = value,
+			"ARTIST" => metadata.artist = value,
+			"ALBUM" => metadata.album = value,
+			"ALBUMARTIST" => metadata.album_artist = value,
+			"TRACKNUMBER" => metadata.track_number = value,
+			"DATE" => metadata.date = value,
+			"GENRE" => metadata.genre = value,
+			"METADATA_BLOCK_PICTURE" => {
+				let picture = value.and_then(|v| base64::decode(&v).ok());
+				if let Some((picture_type, cover_art)) = picture.as_ref().and_then(|p| decode_flac_picture(p)) {
+					metadata.offer_cover_art(picture_type, cover_art);
+				}
+			},
+			_ => {}
+		}
+	}
+
+	Some(metadata)
+}
+
+/// Reads the Vorbis comments from the second
+/// packet of an Ogg Vorbis or Ogg Opus stream.
+fn read_ogg<R>(reader: &mut R) -> Option<TrackMetadata> where R: Read + Seek {
+	let mut packets = ::ogg::PacketReader::new(reader);
+	packets.read_packet_expected().ok()?;
+	let comments = packets.read_packet_expected().ok()?.data;
+	if comments.starts_with(b"\x03vorbis") {
+		decode_vorbis_comments(&comments[7..])
+	} else if comments.starts_with(b"OpusTags") {
+		decode_vorbis_comments(&comments[8..])
+	} else {
+		None
+	}
+}
+
+// FLAC
+
+/// Reads the Vorbis comment and picture
+/// metadata blocks of a FLAC file.
+fn read_flac<R>(reader: &mut R) -> Option<TrackMetadata> where R: Read + Seek {
+	let mut magic = [0u8; 4];
+	reader.read_exact(&mut magic).ok()?;
+	if &magic != b"fLaC" {
+		return None;
+	}
+
+	let mut metadata = TrackMetadata::default();
+	let mut cover_art = None;
+	loop {
+		let mut header = [0u8; 4];
+		reader.read_exact(&mut header).ok()?;
+		let is_last = header[0] & 0x80 != 0;
+		let block_type = header[0] & 0x7F;
+		let len = BigEndian::read_uint(&header[1..4], 3);
+
+		match block_type {
+			4 | 6 => match read_or_skip(reader, len).ok()? {
+				Some(ref block) if block_type == 4 => metadata = decode_vorbis_comments(block)?.or(metadata),
+				Some(ref block) => if let Some(picture) = decode_flac_picture(block) {
+					if cover_art.is_none() || picture.0 == FRONT_COVER {
+						cover_art = Some(picture.1);
+					}
+				},
+				None => {}
+			},
+			// Skip stream info, seek tables, padding etc.
+			_ => { reader.seek(SeekFrom::Current(len as i64)).ok()?; }
+		}
+
+		if is_last {
+			break;
+		}
+	}
+
+	metadata.cover_art = metadata.cover_art.or(cover_art);
+	Some(metadata)
+}
+
+// RIFF
+
+/// Reads the INFO list and an embedded ID3v2
+/// tag (if present) of a RIFF WAV file.
+fn read_riff<R>(reader: &mut R) -> Option<TrackMetadata> where R: Read + Seek {
+	let mut header = [0u8; 12];
+	reader.read_exact(&mut header).ok()?;
+	if &header[0..4] != b"RIFF" || &header[8..12] != b"WAVE" {
+		return None;
+	}
+
+	let mut info = TrackMetadata::default();
+	let mut id3 = TrackMetadata::default();
+	let mut chunk_header = [0u8; 8];
+	while reader.read_exact(&mut chunk_header).is_ok() {
+		let id = &chunk_header[0..4];
+		// Chunks are padded to an even length
+		let size = LittleEndian::read_u32(&chunk_header[4..8]) as u64;
+		let padded_size = size + (size & 1);
+
+		if id == b"LIST" || id == b"id3 " || id == b"ID3 " {
+			let chunk = match read_or_skip(reader, padded_size).ok()? {
+				Some(chunk) => chunk,
+				None => continue
+			};
+			if id == b"LIST" {
+				if chunk.starts_with(b"INFO") {
+					info = decode_riff_info(&chunk[4..]);
+				}
+			} else if chunk.len() >= 10 && chunk.starts_with(b"ID3") {
+				let tag_len = syncsafe(&chunk[6..10]).min(chunk.len() - 10);
+				id3 = decode_id3v2(chunk[3], chunk[5], chunk[10..10 + tag_len].to_vec());
+			}
+		} else {
+			// Skip audio data and other chunks without reading them,
+			// seeking past the end makes the next read fail
+			reader.seek(SeekFrom::Current(padded_size as i64)).ok()?;
+		}
+	}
+
+	Some(id3.or(info))
+}
+
+/// Decodes the subchunks of a RIFF INFO list.
+fn decode_riff_info(data: &[u8]) -> TrackMetadata {
+	let mut metadata = TrackMetadata::default();
+	let mut pos = 0;
+	while let (Some(id), Some(size)) = (take(data, &mut pos, 4), take(data, &mut pos, 4)) {
+		let size = LittleEndian::read_u32(size) as usize;
+		let value = match take(data, &mut pos, size) {
+			Some(value) => non_empty(String::from_utf8_lossy(value).into_owned()),
+			None => break
+		};
+		pos += size & 1;
+		match id {
+			b"INAM" => metadata.title = value,
+			b"IART" => metadata.artist = value,
+			b"IPRD" => metadata.album = value,
+			b"ITRK" | b"IPRT" => metadata.track_number = value,
+			b"ICRD" => metadata.date = value,
+			b"IGNR" => metadata.genre = value,
+			_ => {}
+		}
+	}
+	metadata
+}
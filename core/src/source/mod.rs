@@ -1,10 +1,18 @@
 //! A collection of audio sources.
 
+pub mod channels;
 pub mod mp3;
+pub mod mpeg;
+pub mod wav;
+pub mod flac;
+pub mod ogg;
 pub mod file;
+pub mod format;
+pub mod metadata;
 pub mod command;
 pub mod pausable;
 pub mod conv;
+pub mod resample;
 
 use std::marker::PhantomData;
 use dsp::{Signal, Frame};
@@ -17,6 +25,13 @@ pub trait AudioSource: Signal {
 	fn sample_hz(&self) -> f64;
 }
 
+/// A source that can jump to an arbitrary
+/// position within its stream.
+pub trait Seekable {
+	/// Seeks to the given position in seconds.
+	fn seek_secs(&mut self, secs: f64) -> Result<(), String>;
+}
+
 impl<L, R, F> AudioSource for Either<L, R> where L: AudioSource<Frame=F>, R: AudioSource<Frame=F>, F: Frame {
 	fn sample_hz(&self) -> f64 {
 		match *self {
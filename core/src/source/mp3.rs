@@ -1,34 +1,94 @@
 use super::AudioSource;
-use crate::audioformat::{StandardFrame, STANDARD_CHANNELS};
+use super::channels::ChannelMapper;
+use crate::audioformat::StandardFrame;
 use std::io::Read;
-use dsp::{Sample, Frame, Signal};
+use std::sync::Arc;
+use dsp::{Frame, Signal};
 
 /// An MP3 decoder.
 pub struct Mp3Source<R> {
 	decoder: minimp3::Decoder<R>,
 	sample_rate: f64,
 	eof: bool,
+	error: Option<Arc<str>>,
+	/// Maps the current MP3 frame's channels, which
+	/// may change between frames
+	channel_mapper: ChannelMapper,
 	mp3_frame_data: Vec<i16>,
 	mp3_frame_offset: usize
 }
 
 impl<R> Mp3Source<R> where R: Read {
-	pub fn new(reader: R) -> Mp3Source<R> {
+	pub fn new(reader: R) -> Result<Mp3Source<R>, String> {
 		let mut decoder = minimp3::Decoder::new(reader);
-		let initial_frame = decoder.next_frame().expect("Could not read initial frame from MP3"); // TODO: Handle errors
-		
-		if initial_frame.channels != STANDARD_CHANNELS {
-			// TODO
-			panic!("MP3 has {} channels, while only exactly {} are supported currently", initial_frame.channels, STANDARD_CHANNELS);
+		let initial_frame = match decoder.next_frame() {
+			Ok(frame) => frame,
+			Err(minimp3::Error::Eof) => return Err("MP3 does not contain any frames".to_owned()),
+			Err(e) => return Err(format!("Could not read initial frame from MP3: {}", e))
+		};
+
+		if initial_frame.channels == 0 {
+			return Err("Initial MP3 frame has no channels".to_owned());
 		}
 
-		Mp3Source {
+		Ok(Mp3Source {
 			decoder: decoder,
 			sample_rate: initial_frame.sample_rate as f64,
 			eof: false,
+			error: None,
+			channel_mapper: ChannelMapper::new(initial_frame.channels),
 			mp3_frame_data: initial_frame.data,
 			mp3_frame_offset: 0
+		})
+	}
+
+	/// Fetches the error that caused decoding
+	/// to stop prematurely, if any.
+	pub fn error(&self) -> Option<&Arc<str>> { self.error.as_ref() }
+
+	/// Decodes the next MP3 frame into the frame buffer.
+	/// Returns false if the end of the stream was reached
+	/// or an unrecoverable error occurred.
+	fn read_mp3_frame(&mut self) -> bool {
+		loop {
+			match self.decoder.next_frame() {
+				Ok(minimp3::Frame { channels: 0, .. }) => {},
+				Ok(minimp3::Frame { data, channels, .. }) => {
+					if channels != self.channel_mapper.channels() {
+						self.channel_mapper = ChannelMapper::new(channels);
+					}
+					self.mp3_frame_data = data;
+					self.mp3_frame_offset = 0;
+					return true;
+				},
+				Err(minimp3::Error::Eof) => break,
+				Err(e) => {
+					self.error = Some(format!("Could not decode MP3 frame: {}", e).into());
+					break;
+				}
+			}
+		}
+		self.eof = true;
+		false
+	}
+
+	/// Skips the given number of frames, discarding
+	/// whole MP3 frames where possible. Returns the
+	/// number of frames that were actually skipped.
+	pub fn skip(&mut self, frames: u64) -> u64 {
+		let mut skipped = 0;
+		while skipped < frames && !self.eof {
+			let channels = self.channel_mapper.channels();
+			let available = ((self.mp3_frame_data.len() - self.mp3_frame_offset) / channels) as u64;
+			if available == 0 {
+				self.read_mp3_frame();
+			} else {
+				let step = available.min(frames - skipped);
+				self.mp3_frame_offset += step as usize * channels;
+				skipped += step;
+			}
 		}
+		skipped
 	}
 }
 
@@ -40,32 +100,19 @@ impl<R> Signal for Mp3Source<R> where R: Read {
 	type Frame = StandardFrame;
 
 	fn next(&mut self) -> StandardFrame {
-		if self.eof {
-			StandardFrame::equilibrium()
-		} else {
-			let mp3_frame_size = self.mp3_frame_data.len();
-
-			if self.mp3_frame_offset == mp3_frame_size {
-				self.mp3_frame_data = match self.decoder.next_frame() {
-					Ok(minimp3::Frame { data, .. }) => data, // TODO: Deal with custom channel counts
-					Err(minimp3::Error::Eof) => {
-						self.eof = true;
-						return StandardFrame::equilibrium();
-					},
-					Err(e) => panic!("{:?}", e) // TODO: Handle errors
-				};
-				self.mp3_frame_offset = 0
+		// Frames may be empty, thus we loop
+		while self.mp3_frame_offset >= self.mp3_frame_data.len() {
+			if self.eof || !self.read_mp3_frame() {
+				return StandardFrame::equilibrium();
 			}
-			
-			let frame: StandardFrame = [
-				self.mp3_frame_data[self.mp3_frame_offset].to_sample(),
-				self.mp3_frame_data[self.mp3_frame_offset + 1].to_sample()
-			];
-
-			self.mp3_frame_offset += STANDARD_CHANNELS;
-			frame
 		}
+
+		let channels = self.channel_mapper.channels();
+		let frame = self.channel_mapper.map(&self.mp3_frame_data[self.mp3_frame_offset..self.mp3_frame_offset + channels]);
+
+		self.mp3_frame_offset += channels;
+		frame
 	}
-	
+
 	fn is_exhausted(&self) -> bool { self.eof }
 }
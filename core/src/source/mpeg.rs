@@ -0,0 +1,233 @@
+//! MPEG audio frame header parsing, used to
+//! determine the length of MP3 files without
+//! decoding them.
+
+use std::io::{self, Read, Seek, SeekFrom};
+use byteorder::{BigEndian, ByteOrder};
+
+const BITRATES_KBPS_V1: [[u32; 15]; 3] = [
+	[0, 32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448], // Layer I
+	[0, 32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384], // Layer II
+	[0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320] // Layer III
+];
+const BITRATES_KBPS_V2: [[u32; 15]; 3] = [
+	[0, 32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256], // Layer I
+	[0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160], // Layer II
+	[0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160] // Layer III
+];
+const SAMPLE_RATES_V1: [u32; 3] = [44_100, 48_000, 32_000];
+
+/// The MPEG version of a frame.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MpegVersion {
+	V1,
+	V2,
+	V25
+}
+
+/// A parsed MPEG audio frame header.
+#[derive(Debug, Copy, Clone)]
+pub struct FrameHeader {
+	pub version: MpegVersion,
+	/// The layer (1, 2 or 3)
+	pub layer: u8,
+	pub bitrate_kbps: u32,
+	pub sample_rate: u32,
+	pub padding: bool,
+	pub channels: usize
+}
+
+impl FrameHeader {
+	/// Parses a 4-byte frame header, returning
+	/// None if it is invalid.
+	pub fn parse(bytes: [u8; 4]) -> Option<FrameHeader> {
+		let header = BigEndian::read_u32(&bytes);
+		if header & 0xFFE0_0000 != 0xFFE0_0000 {
+			return None;
+		}
+		let version = match (header >> 19) & 0b11 {
+			0b00 => MpegVersion::V25,
+			0b10 => MpegVersion::V2,
+			0b11 => MpegVersion::V1,
+			_ => return None
+		};
+		let layer = match (header >> 17) & 0b11 {
+			0b01 => 3,
+			0b10 => 2,
+			0b11 => 1,
+			_ => return None
+		};
+		let bitrate_index = ((header >> 12) & 0b1111) as usize;
+		let sample_rate_index = ((header >> 10) & 0b11) as usize;
+		if bitrate_index == 0 || bitrate_index == 15 || sample_rate_index == 3 {
+			// Free-format and reserved values are not supported
+			return None;
+		}
+		let bitrates = if version == MpegVersion::V1 { &BITRATES_KBPS_V1 } else { &BITRATES_KBPS_V2 };
+		let sample_rate = match version {
+			MpegVersion::V1 => SAMPLE_RATES_V1[sample_rate_index],
+			MpegVersion::V2 => SAMPLE_RATES_V1[sample_rate_index] / 2,
+			MpegVersion::V25 => SAMPLE_RATES_V1[sample_rate_index] / 4
+		};
+		Some(FrameHeader {
+			version: version,
+			layer: layer,
+			bitrate_kbps: bitrates[layer as usize - 1][bitrate_index],
+			sample_rate: sample_rate,
+			padding: (header >> 9) & 1 == 1,
+			channels: if (header >> 6) & 0b11 == 0b11 { 1 } else { 2 }
+		})
+	}
+
+	/// The number of (multichannel) samples per frame.
+	pub fn samples_per_frame(&self) -> u32 {
+		match (self.layer, self.version) {
+			(1, _) => 384,
+			(3, MpegVersion::V2) | (3, MpegVersion::V25) => 576,
+			_ => 1152
+		}
+	}
+
+	/// The length of the frame in bytes, including
+	/// the header.
+	pub fn frame_bytes(&self) -> u32 {
+		let padding = if self.padding { 1 } else { 0 };
+		if self.layer == 1 {
+			(12 * self.bitrate_kbps * 1000 / self.sample_rate + padding) * 4
+		} else {
+			self.samples_per_frame() / 8 * self.bitrate_kbps * 1000 / self.sample_rate + padding
+		}
+	}
+
+	/// The offset of the Xing/Info header
+	/// relative to the start of the frame.
+	fn xing_offset(&self) -> usize {
+		4 + match (self.version, self.channels) {
+			(MpegVersion::V1, 1) => 17,
+			(MpegVersion::V1, _) => 32,
+			(_, 1) => 9,
+			(_, _) => 17
+		}
+	}
+}
+
+/// Skips an ID3v2 tag at the current position if present.
+fn skip_id3v2<R>(reader: &mut R) -> io::Result<()> where R: Read + Seek {
+	let mut header = [0u8; 10];
+	let start = reader.seek(SeekFrom::Current(0))?;
+	if reader.read_exact(&mut header).is_ok() && &header[0..3] == b"ID3" {
+		// The size is stored as a 'syncsafe' integer
+		let size = header[6..10].iter().fold(0u64, |acc, &b| (acc << 7) | (b & 0x7F) as u64);
+		let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+		reader.seek(SeekFrom::Start(start + 10 + size + footer))?;
+	} else {
+		reader.seek(SeekFrom::Start(start))?;
+	}
+	Ok(())
+}
+
+/// Checks whether the given bytes start a tag that is
+/// appended to MPEG streams (ID3v1, APEv2 or Lyrics3),
+/// reading the rest of its identifier if needed. Since
+/// frames start with a sync byte, skipping these bytes
+/// when there is no tag does not skip a frame.
+fn is_trailing_tag<R>(start: [u8; 4], reader: &mut R) -> bool where R: Read {
+	let rest: &[u8] = match &start {
+		b if &b[0..3] == b"TAG" => return true,
+		b"APET" => &b"AGEX"[..],
+		b"LYRI" => &b"CS"[..],
+		_ => return false
+	};
+	let mut buffer = [0u8; 4];
+	let buffer = &mut buffer[..rest.len()];
+	reader.read_exact(buffer).is_ok() && &buffer[..] == rest
+}
+
+/// Reads the frame count from a Xing/Info or VBRI
+/// header inside the given (first) frame.
+fn read_vbr_frame_count(header: &FrameHeader, frame: &[u8]) -> Option<u32> {
+	let xing = header.xing_offset();
+	if frame.len() >= xing + 12 && (&frame[xing..xing + 4] == b"Xing" || &frame[xing..xing + 4] == b"Info") {
+		let flags = BigEndian::read_u32(&frame[xing + 4..xing + 8]);
+		if flags & 1 != 0 {
+			return Some(BigEndian::read_u32(&frame[xing + 8..xing + 12]));
+		}
+	}
+	let vbri = 4 + 32;
+	if frame.len() >= vbri + 18 && &frame[vbri..vbri + 4] == b"VBRI" {
+		return Some(BigEndian::read_u32(&frame[vbri + 14..vbri + 18]));
+	}
+	None
+}
+
+/// Determines the length of an MPEG audio stream in
+/// (multichannel) samples, either from a Xing/Info/VBRI
+/// header or, if absent, by scanning all frame headers.
+/// The reader is rewound afterwards.
+pub fn duration_frames<R>(reader: &mut R) -> Option<u64> where R: Read + Seek {
+	let start = reader.seek(SeekFrom::Current(0)).ok()?;
+	let duration = scan_duration_frames(reader);
+	reader.seek(SeekFrom::Start(start)).ok()?;
+	duration
+}
+
+fn scan_duration_frames<R>(reader: &mut R) -> Option<u64> where R: Read + Seek {
+	skip_id3v2(reader).ok()?;
+
+	let mut total = 0u64;
+	let mut first = true;
+	let mut bytes = [0u8; 4];
+	let mut frame = Vec::new();
+
+	if reader.read_exact(&mut bytes).is_err() {
+		return None;
+	}
+	loop {
+		let header = FrameHeader::parse(bytes).filter(|header| header.frame_bytes() >= 4);
+		let header = match header {
+			Some(header) => header,
+			None => {
+				if is_trailing_tag(bytes, reader) {
+					// Reached an ID3v1, APEv2 or Lyrics3
+					// tag at the end of the file
+					break;
+				}
+				// Resynchronize byte-by-byte. Shifting the
+				// window (rather than seeking back) keeps a
+				// buffered reader from discarding its buffer.
+				bytes.rotate_left(1);
+				if reader.read_exact(&mut bytes[3..]).is_err() {
+					break;
+				}
+				continue;
+			}
+		};
+		let frame_bytes = header.frame_bytes() as usize;
+
+		if first {
+			first = false;
+			frame.resize(frame_bytes, 0);
+			frame[0..4].copy_from_slice(&bytes);
+			if reader.read_exact(&mut frame[4..]).is_err() {
+				break;
+			}
+			if let Some(frame_count) = read_vbr_frame_count(&header, &frame) {
+				return Some(frame_count as u64 * header.samples_per_frame() as u64);
+			}
+		} else {
+			// Read past the frame's data instead of seeking
+			// over it, for the same reason as above
+			let skipped = io::copy(&mut reader.by_ref().take(frame_bytes as u64 - 4), &mut io::sink()).ok()?;
+			if skipped < frame_bytes as u64 - 4 {
+				break;
+			}
+		}
+		total += header.samples_per_frame() as u64;
+
+		if reader.read_exact(&mut bytes).is_err() {
+			break;
+		}
+	}
+
+	if total > 0 { Some(total) } else { None }
+}
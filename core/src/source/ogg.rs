@@ -0,0 +1,282 @@
+//! Decoders for audio streams inside the
+//! Ogg container format.
+
+use super::AudioSource;
+use super::channels::ChannelMapper;
+use crate::audioformat::{StandardFrame, STANDARD_CHANNELS};
+use std::io::{Read, Seek, SeekFrom};
+use std::sync::Arc;
+use byteorder::{LittleEndian, ByteOrder};
+use dsp::{Frame, Signal};
+use log::warn;
+use lewton::VorbisError;
+use lewton::inside_ogg::OggStreamReader;
+
+/// The codec of a (logical) Ogg stream.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OggCodec {
+	Vorbis,
+	Opus
+}
+
+/// Opus always decodes to 48 kHz, regardless
+/// of the input sample rate stored in the header.
+const OPUS_SAMPLE_HZ: u32 = 48_000;
+/// The maximum duration of an Opus packet (120 ms)
+/// in frames at 48 kHz.
+const OPUS_MAX_PACKET_FRAMES: usize = 5760;
+
+/// Identifies the codec of the first stream in an Ogg
+/// file by peeking at the beginning-of-stream page. The
+/// reader is rewound afterwards.
+pub fn identify_codec<R>(reader: &mut R) -> Result<OggCodec, String> where R: Read + Seek {
+	let start = reader.seek(SeekFrom::Current(0)).map_err(|e| format!("{:?}", e))?;
+	let codec = read_codec(reader);
+	reader.seek(SeekFrom::Start(start)).map_err(|e| format!("{:?}", e))?;
+	codec
+}
+
+fn read_codec<R>(reader: &mut R) -> Result<OggCodec, String> where R: Read {
+	let mut header = [0u8; 27];
+	reader.read_exact(&mut header).map_err(|e| format!("Could not read Ogg page header: {:?}", e))?;
+	if &header[0..4] != b"OggS" {
+		return Err("Missing Ogg capture pattern".to_owned());
+	}
+
+	// Skip the segment table, the first packet
+	// then immediately follows
+	let mut segment_table = vec![0u8; header[26] as usize];
+	reader.read_exact(&mut segment_table).map_err(|e| format!("Could not read Ogg segment table: {:?}", e))?;
+
+	let mut magic = [0u8; 8];
+	reader.read_exact(&mut magic).map_err(|e| format!("Could not read Ogg codec header: {:?}", e))?;
+	if &magic[0..7] == b"\x01vorbis" {
+		Ok(OggCodec::Vorbis)
+	} else if &magic == b"OpusHead" {
+		Ok(OggCodec::Opus)
+	} else {
+		Err("Unsupported codec in Ogg stream".to_owned())
+	}
+}
+
+/// Finds the granule position of the last page in an
+/// Ogg file, i.e. the total number of (multichannel)
+/// samples of the stream including any pre-skip.
+/// The reader is rewound afterwards.
+pub fn last_granule_position<R>(reader: &mut R) -> Option<u64> where R: Read + Seek {
+	let start = reader.seek(SeekFrom::Current(0)).ok()?;
+	let granule = read_last_granule_position(reader);
+	reader.seek(SeekFrom::Start(start)).ok()?;
+	granule
+}
+
+fn read_last_granule_position<R>(reader: &mut R) -> Option<u64> where R: Read + Seek {
+	// An Ogg page is at most 65307 bytes long, thus
+	// the last page header has to be contained in
+	// the tail of this length
+	let len = reader.seek(SeekFrom::End(0)).ok()?;
+	let tail_len = len.min(65_307);
+	reader.seek(SeekFrom::Start(len - tail_len)).ok()?;
+	let mut tail = Vec::with_capacity(tail_len as usize);
+	reader.read_to_end(&mut tail).ok()?;
+
+	(0..tail.len().saturating_sub(14)).rev()
+		.filter(|&i| &tail[i..i + 4] == b"OggS")
+		.map(|i| LittleEndian::read_u64(&tail[i + 6..i + 14]))
+		// A granule position of -1 indicates that no packet finishes on the page
+		.find(|&granule| granule != u64::max_value())
+}
+
+/// An Ogg Vorbis decoder.
+pub struct VorbisSource<R> where R: Read + Seek {
+	reader: OggStreamReader<R>,
+	sample_rate: f64,
+	channel_mapper: ChannelMapper,
+	eof: bool,
+	error: Option<Arc<str>>,
+	vorbis_frame_data: Vec<i16>,
+	vorbis_frame_offset: usize
+}
+
+impl<R> VorbisSource<R> where R: Read + Seek {
+	pub fn new(reader: R) -> Result<VorbisSource<R>, String> {
+		let reader = OggStreamReader::new(reader).map_err(|e| format!("Could not read Vorbis headers: {:?}", e))?;
+		Ok(VorbisSource {
+			sample_rate: reader.ident_hdr.audio_sample_rate as f64,
+			channel_mapper: ChannelMapper::vorbis(reader.ident_hdr.audio_channels as usize),
+			reader: reader,
+			eof: false,
+			error: None,
+			vorbis_frame_data: Vec::new(),
+			vorbis_frame_offset: 0
+		})
+	}
+
+	/// Fetches the error that caused decoding
+	/// to stop prematurely, if any.
+	pub fn error(&self) -> Option<&Arc<str>> { self.error.as_ref() }
+}
+
+impl<R> AudioSource for VorbisSource<R> where R: Read + Seek {
+	fn sample_hz(&self) -> f64 { self.sample_rate }
+}
+
+impl<R> Signal for VorbisSource<R> where R: Read + Seek {
+	type Frame = StandardFrame;
+
+	fn next(&mut self) -> StandardFrame {
+		if self.eof {
+			return StandardFrame::equilibrium();
+		}
+
+		// Packets may decode to zero samples (e.g. the
+		// first audio packet), thus we loop
+		while self.vorbis_frame_offset >= self.vorbis_frame_data.len() {
+			self.vorbis_frame_data = match self.reader.read_dec_packet_itl() {
+				Ok(Some(data)) => data,
+				Ok(None) => {
+					self.eof = true;
+					return StandardFrame::equilibrium();
+				},
+				Err(VorbisError::BadAudio(e)) => {
+					warn!("Skipping corrupt Vorbis packet: {:?}", e);
+					Vec::new()
+				},
+				Err(e) => {
+					self.error = Some(format!("Could not decode Vorbis packet: {:?}", e).into());
+					self.eof = true;
+					return StandardFrame::equilibrium();
+				}
+			};
+			self.vorbis_frame_offset = 0;
+		}
+
+		let channels = self.channel_mapper.channels();
+		let frame = self.channel_mapper.map(&self.vorbis_frame_data[self.vorbis_frame_offset..self.vorbis_frame_offset + channels]);
+
+		self.vorbis_frame_offset += channels;
+		frame
+	}
+
+	fn is_exhausted(&self) -> bool { self.eof }
+}
+
+/// An Ogg Opus decoder. Mono streams are decoded
+/// to stereo by libopus.
+pub struct OpusSource<R> where R: Read + Seek {
+	reader: ::ogg::PacketReader<R>,
+	decoder: ::opus::Decoder,
+	/// The linear output gain from the header
+	gain: f32,
+	/// The number of frames to be discarded from
+	/// the beginning of the stream, as stored in the header
+	header_pre_skip: usize,
+	/// The number of frames still to be discarded
+	pre_skip: usize,
+	eof: bool,
+	error: Option<Arc<str>>,
+	opus_frame_data: Vec<f32>,
+	opus_frame_len: usize,
+	opus_frame_offset: usize
+}
+
+impl<R> OpusSource<R> where R: Read + Seek {
+	pub fn new(reader: R) -> Result<OpusSource<R>, String> {
+		let mut reader = ::ogg::PacketReader::new(reader);
+
+		// Parse the identification header
+		let head = reader.read_packet_expected().map_err(|e| format!("Could not read Opus header: {:?}", e))?.data;
+		if head.len() < 19 || &head[0..8] != b"OpusHead" {
+			return Err("Invalid Opus header".to_owned());
+		}
+		let channels = head[9];
+		let pre_skip = LittleEndian::read_u16(&head[10..12]);
+		let output_gain = LittleEndian::read_i16(&head[16..18]);
+		let mapping_family = head[18];
+		if mapping_family != 0 || channels == 0 || channels > 2 {
+			return Err(format!("Opus streams with {} channels (mapping family {}) are not supported", channels, mapping_family));
+		}
+
+		// Skip the comment header
+		reader.read_packet_expected().map_err(|e| format!("Could not read Opus tags: {:?}", e))?;
+
+		Ok(OpusSource {
+			reader: reader,
+			decoder: ::opus::Decoder::new(OPUS_SAMPLE_HZ, ::opus::Channels::Stereo).map_err(|e| format!("Could not create Opus decoder: {:?}", e))?,
+			// The gain is stored as Q7.8 in dB
+			gain: 10f32.powf(output_gain as f32 / (20.0 * 256.0)),
+			header_pre_skip: pre_skip as usize,
+			pre_skip: pre_skip as usize,
+			eof: false,
+			error: None,
+			opus_frame_data: vec![0.0; OPUS_MAX_PACKET_FRAMES * STANDARD_CHANNELS],
+			opus_frame_len: 0,
+			opus_frame_offset: 0
+		})
+	}
+
+	/// The number of frames discarded from the
+	/// beginning of the stream.
+	pub fn header_pre_skip(&self) -> u64 { self.header_pre_skip as u64 }
+	
+	/// Fetches the error that caused decoding
+	/// to stop prematurely, if any.
+	pub fn error(&self) -> Option<&Arc<str>> { self.error.as_ref() }
+	
+	/// Decodes the next packet into the frame buffer.
+	/// Returns false if the end of the stream was reached
+	/// or an unrecoverable error occurred.
+	fn read_packet(&mut self) -> bool {
+		let packet = match self.reader.read_packet() {
+			Ok(Some(packet)) => packet,
+			Ok(None) => return false,
+			Err(e) => {
+				self.error = Some(format!("Could not read Ogg packet: {:?}", e).into());
+				return false;
+			}
+		};
+		let frames = match self.decoder.decode_float(&packet.data, &mut self.opus_frame_data, false) {
+			Ok(frames) => frames,
+			Err(e) => {
+				warn!("Skipping corrupt Opus packet: {:?}", e);
+				0
+			}
+		};
+		let skipped = frames.min(self.pre_skip);
+		self.pre_skip -= skipped;
+		self.opus_frame_len = frames * STANDARD_CHANNELS;
+		self.opus_frame_offset = skipped * STANDARD_CHANNELS;
+		true
+	}
+}
+
+impl<R> AudioSource for OpusSource<R> where R: Read + Seek {
+	fn sample_hz(&self) -> f64 { OPUS_SAMPLE_HZ as f64 }
+}
+
+impl<R> Signal for OpusSource<R> where R: Read + Seek {
+	type Frame = StandardFrame;
+
+	fn next(&mut self) -> StandardFrame {
+		if self.eof {
+			return StandardFrame::equilibrium();
+		}
+
+		while self.opus_frame_offset >= self.opus_frame_len {
+			if !self.read_packet() {
+				self.eof = true;
+				return StandardFrame::equilibrium();
+			}
+		}
+
+		let frame = [
+			self.opus_frame_data[self.opus_frame_offset] * self.gain,
+			self.opus_frame_data[self.opus_frame_offset + 1] * self.gain
+		];
+
+		self.opus_frame_offset += STANDARD_CHANNELS;
+		frame
+	}
+
+	fn is_exhausted(&self) -> bool { self.eof }
+}
@@ -1,5 +1,6 @@
 use dsp::{Signal, Frame};
-use super::AudioSource;
+use crate::util::retargetable::Retargetable;
+use super::{AudioSource, Seekable};
 
 /// A source that can be paused. When paused, the
 /// source will not request any frames from its
@@ -23,6 +24,14 @@ impl<S> AudioSource for Pausable<S> where S: AudioSource {
 	fn sample_hz(&self) -> f64 { self.wrapped.sample_hz() }
 }
 
+impl<S> Seekable for Pausable<S> where S: Seekable {
+	fn seek_secs(&mut self, secs: f64) -> Result<(), String> { self.wrapped.seek_secs(secs) }
+}
+
+impl<S> Retargetable for Pausable<S> where S: Retargetable {
+	fn retarget(&mut self, target_sample_hz: f64) { self.wrapped.retarget(target_sample_hz) }
+}
+
 impl<S> Signal for Pausable<S> where S: Signal {
 	type Frame = S::Frame;
 	
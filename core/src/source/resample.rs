@@ -0,0 +1,121 @@
+//! Interpolators of varying quality used for
+//! sample rate conversion.
+
+use dsp::{Signal, Frame, Sample, DuplexSample};
+use dsp::sample::ring_buffer;
+use dsp::sample::interpolate::{Interpolator, Linear, Sinc};
+
+/// The interpolation algorithm used to resample.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ResampleQuality {
+	/// Fast, but audibly aliases
+	Linear,
+	/// Four-point Catmull-Rom interpolation
+	Cubic,
+	/// Windowed sinc interpolation using the
+	/// given number of taps (frames)
+	Sinc { taps: usize }
+}
+
+impl ResampleQuality {
+	/// The number of frames the resampler reads from its
+	/// source ahead of the frame it interpolates from.
+	pub fn lookahead_frames(&self) -> usize {
+		match *self {
+			ResampleQuality::Linear => 2,
+			ResampleQuality::Cubic => 3,
+			ResampleQuality::Sinc { taps } => sinc_depth(taps)
+		}
+	}
+}
+
+/// The number of taps the sinc interpolator uses
+/// on each side of the interpolated position.
+fn sinc_depth(taps: usize) -> usize { taps.max(2) / 2 }
+
+impl Default for ResampleQuality {
+	fn default() -> ResampleQuality { ResampleQuality::Linear }
+}
+
+/// A cubic (Catmull-Rom spline) interpolator that
+/// interpolates between the middle two of the
+/// last four source frames.
+pub struct Cubic<F> {
+	frames: [F; 4]
+}
+
+impl<F> Cubic<F> where F: Frame {
+	pub fn from_source<S>(signal: &mut S) -> Cubic<F> where S: Signal<Frame=F> {
+		Cubic { frames: [F::equilibrium(), signal.next(), signal.next(), signal.next()] }
+	}
+}
+
+impl<F> Interpolator for Cubic<F> where F: Frame, F::Sample: DuplexSample<f64> {
+	type Frame = F;
+
+	fn interpolate(&self, x: f64) -> F {
+		let sample = |frame: &F, channel: usize| -> f64 { frame.channel(channel).map(|s| s.to_sample()).unwrap_or(0.0) };
+		F::from_fn(|channel| {
+			let p0 = sample(&self.frames[0], channel);
+			let p1 = sample(&self.frames[1], channel);
+			let p2 = sample(&self.frames[2], channel);
+			let p3 = sample(&self.frames[3], channel);
+			let a = -0.5 * p0 + 1.5 * p1 - 1.5 * p2 + 0.5 * p3;
+			let b = p0 - 2.5 * p1 + 2.0 * p2 - 0.5 * p3;
+			let c = -0.5 * p0 + 0.5 * p2;
+			(((a * x + b) * x + c) * x + p1).to_sample()
+		})
+	}
+
+	fn next_source_frame(&mut self, source_frame: F) {
+		self.frames = [self.frames[1], self.frames[2], self.frames[3], source_frame];
+	}
+}
+
+/// A statically dispatched interpolator
+/// of configurable quality.
+pub enum Resampler<F> {
+	Linear(Linear<F>),
+	Cubic(Cubic<F>),
+	Sinc(Sinc<Vec<F>>)
+}
+
+impl<F> Resampler<F> where F: Frame {
+	pub fn from_source<S>(quality: ResampleQuality, signal: &mut S) -> Resampler<F> where S: Signal<Frame=F> {
+		match quality {
+			ResampleQuality::Linear => Resampler::Linear(Linear::from_source(signal)),
+			ResampleQuality::Cubic => Resampler::Cubic(Cubic::from_source(signal)),
+			ResampleQuality::Sinc { taps } => {
+				let depth = sinc_depth(taps);
+				let mut sinc = Sinc::new(ring_buffer::Fixed::from(vec![F::equilibrium(); 2 * depth]));
+				// Prime the frames ahead of the interpolated
+				// position, which otherwise delays the output
+				// by silence (e.g. after every seek)
+				for _ in 0..depth {
+					sinc.next_source_frame(signal.next());
+				}
+				Resampler::Sinc(sinc)
+			}
+		}
+	}
+}
+
+impl<F> Interpolator for Resampler<F> where F: Frame, F::Sample: DuplexSample<f64> {
+	type Frame = F;
+
+	fn interpolate(&self, x: f64) -> F {
+		match *self {
+			Resampler::Linear(ref interpolator) => interpolator.interpolate(x),
+			Resampler::Cubic(ref interpolator) => interpolator.interpolate(x),
+			Resampler::Sinc(ref interpolator) => interpolator.interpolate(x)
+		}
+	}
+
+	fn next_source_frame(&mut self, source_frame: F) {
+		match *self {
+			Resampler::Linear(ref mut interpolator) => interpolator.next_source_frame(source_frame),
+			Resampler::Cubic(ref mut interpolator) => interpolator.next_source_frame(source_frame),
+			Resampler::Sinc(ref mut interpolator) => interpolator.next_source_frame(source_frame)
+		}
+	}
+}
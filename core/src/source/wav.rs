@@ -0,0 +1,111 @@
+use super::AudioSource;
+use super::channels::ChannelMapper;
+use crate::audioformat::{StandardFrame, StandardSample};
+use std::io::{Read, Seek};
+use std::sync::Arc;
+use dsp::{Frame, Signal};
+use hound::{WavReader, SampleFormat};
+
+/// A RIFF WAV decoder supporting 8/16/24/32-bit
+/// integer and 32-bit floating point PCM with
+/// an arbitrary number of channels.
+pub struct WavSource<R> {
+	reader: WavReader<R>,
+	sample_rate: f64,
+	channels: usize,
+	channel_mapper: ChannelMapper,
+	sample_format: SampleFormat,
+	/// The factor that normalizes integer samples to [-1.0, 1.0]
+	int_scale: StandardSample,
+	eof: bool,
+	error: Option<Arc<str>>,
+	wav_frame_data: Vec<StandardSample>
+}
+
+impl<R> WavSource<R> where R: Read {
+	pub fn new(reader: R) -> Result<WavSource<R>, String> {
+		let reader = WavReader::new(reader).map_err(|e| format!("Could not read WAV header: {}", e))?;
+		let spec = reader.spec();
+
+		if spec.channels == 0 {
+			return Err("WAV file has no channels".to_owned());
+		}
+
+		Ok(WavSource {
+			reader: reader,
+			sample_rate: spec.sample_rate as f64,
+			channels: spec.channels as usize,
+			channel_mapper: ChannelMapper::new(spec.channels as usize),
+			sample_format: spec.sample_format,
+			int_scale: 1.0 / ((1u64 << (spec.bits_per_sample - 1)) as StandardSample),
+			eof: false,
+			error: None,
+			wav_frame_data: Vec::with_capacity(spec.channels as usize)
+		})
+	}
+
+	/// The length of the file in frames.
+	pub fn duration_frames(&self) -> u64 { self.reader.duration() as u64 }
+
+	/// Fetches the error that caused decoding
+	/// to stop prematurely, if any.
+	pub fn error(&self) -> Option<&Arc<str>> { self.error.as_ref() }
+
+	fn read_sample(&mut self) -> Option<StandardSample> {
+		let int_scale = self.int_scale;
+		let sample = match self.sample_format {
+			SampleFormat::Float => self.reader.samples::<f32>().next()?,
+			SampleFormat::Int => self.reader.samples::<i32>().next()?.map(|s| s as StandardSample * int_scale)
+		};
+		match sample {
+			Ok(sample) => Some(sample),
+			Err(e) => {
+				self.error = Some(format!("Could not read WAV sample: {}", e).into());
+				None
+			}
+		}
+	}
+
+	/// Reads a single multichannel frame into the
+	/// frame buffer.
+	fn read_wav_frame(&mut self) -> Option<()> {
+		self.wav_frame_data.clear();
+		for _ in 0..self.channels {
+			let sample = self.read_sample()?;
+			self.wav_frame_data.push(sample);
+		}
+		Some(())
+	}
+}
+
+impl<R> WavSource<R> where R: Read + Seek {
+	/// Jumps to the given frame, clamped to the
+	/// length of the file. Returns the new position.
+	pub fn seek(&mut self, frame: u64) -> Result<u64, String> {
+		let frame = frame.min(self.reader.duration() as u64);
+		self.reader.seek(frame as u32).map_err(|e| format!("{:?}", e))?;
+		self.eof = false;
+		Ok(frame)
+	}
+}
+
+impl<R> AudioSource for WavSource<R> where R: Read {
+	fn sample_hz(&self) -> f64 { self.sample_rate }
+}
+
+impl<R> Signal for WavSource<R> where R: Read {
+	type Frame = StandardFrame;
+
+	fn next(&mut self) -> StandardFrame {
+		if self.eof {
+			StandardFrame::equilibrium()
+		} else if self.read_wav_frame().is_none() {
+			self.eof = true;
+			StandardFrame::equilibrium()
+		} else {
+			self.channel_mapper.map(&self.wav_frame_data)
+		}
+	}
+
+	fn is_exhausted(&self) -> bool { self.eof }
+}
@@ -0,0 +1,33 @@
+//! Helpers shared by the graph and history tests.
+
+use dsp::Node;
+use crate::audioformat::StandardFrame;
+use crate::graph::{AudioGraph, NodeId, EdgeId};
+
+/// A node that is identified by its value.
+#[derive(Debug, PartialEq)]
+pub struct TestNode(pub u32);
+
+impl Node<StandardFrame> for TestNode {
+	fn audio_requested(&mut self, _buffer: &mut [StandardFrame], _sample_hz: f64) {}
+}
+
+/// The nodes, edges, names and master of a graph.
+pub type Snapshot = (Vec<(NodeId, u32)>, Vec<(EdgeId, NodeId, NodeId)>, Vec<(NodeId, String)>, Option<NodeId>);
+
+pub fn snapshot(graph: &AudioGraph<TestNode>) -> Snapshot {
+	(
+		graph.nodes().map(|(id, node)| (id, node.0)).collect(),
+		graph.edges().collect(),
+		graph.names().map(|(id, name)| (id, name.to_string())).collect(),
+		graph.master()
+	)
+}
+
+/// Creates a graph with the nodes 0 to `count - 1`,
+/// each holding its identifier.
+pub fn graph_with_nodes(count: u32) -> (AudioGraph<TestNode>, Vec<NodeId>) {
+	let mut graph = AudioGraph::new();
+	let ids = (0..count).map(|i| graph.add_node(TestNode(i))).collect();
+	(graph, ids)
+}
@@ -0,0 +1,15 @@
+/// Indicates that this type might produce
+/// a finite amount of audio.
+pub trait Exhaustible {
+	/// Whether this produces audio on its own
+	/// (as opposed to only transforming its input).
+	fn is_source(&self) -> bool;
+	
+	/// Whether this will not produce any further
+	/// audio. Non-sources are always exhausted.
+	fn is_exhausted(&self) -> bool;
+	
+	/// Whether this source is paused and thus, until
+	/// it is resumed, only produces silence.
+	fn is_paused(&self) -> bool { false }
+}
@@ -1,2 +1,4 @@
 pub mod either;
 pub mod empty;
+pub mod exhaustible;
+pub mod retargetable;
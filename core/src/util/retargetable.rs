@@ -0,0 +1,10 @@
+/// Indicates that this type depends on the output
+/// sample rate, which might change while the engine
+/// is running (e.g. when the JACK server switches rates).
+pub trait Retargetable {
+	/// Adapts this to a new output sample rate. This runs
+	/// on the render thread and may allocate (e.g. to refill
+	/// a resampler), which is acceptable since rate changes
+	/// are rare.
+	fn retarget(&mut self, target_sample_hz: f64);
+}
@@ -0,0 +1,137 @@
+use jsonrpc_core::Result as RpcResult;
+use jsonrpc_derive::rpc;
+use serde::{Serialize, Deserialize};
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+/// An output format supported by a device.
+pub struct RpcFormat {
+	pub channels: u16,
+	pub min_sample_hz: u32,
+	pub max_sample_hz: u32,
+	pub sample_format: String
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+/// An output device.
+pub struct RpcDevice {
+	pub name: String,
+	pub is_default: bool,
+	pub formats: Vec<RpcFormat>
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+/// An audio host, i.e. a backend such as ALSA or WASAPI.
+pub struct RpcHost {
+	pub name: String,
+	pub is_default: bool,
+	pub devices: Vec<RpcDevice>
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+/// Runtime statistics of the running engine.
+pub struct RpcEngineStats {
+	/// The number of processed buffers
+	pub callbacks: u64,
+	/// The number of buffers that took longer to
+	/// compute than to play back (likely underruns)
+	pub late_callbacks: u64,
+	/// The number of errors reported by the output stream
+	pub stream_errors: u64,
+	pub frames: u64,
+	pub average_callback_secs: f64,
+	pub max_callback_secs: f64,
+	/// The time spent applying graph modifications
+	/// at the beginning of a buffer
+	pub average_update_secs: f64,
+	pub max_update_secs: f64
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+/// The playback state of the engine.
+pub enum RpcPlaybackState {
+	Playing,
+	Paused,
+	Stopped
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+/// The state and output format of the engine.
+pub struct RpcEngineStatus {
+	pub state: RpcPlaybackState,
+	/// The current output sample rate, which the
+	/// JACK server may change while running
+	pub sample_hz: f64,
+	pub channels: usize,
+	/// The output device (or file), if any
+	pub device: Option<String>
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+/// The output to restart the speaker engine on. Omitted
+/// fields fall back to the host's defaults.
+pub struct RpcSpeakerOutput {
+	/// The name of the audio host, see `engine.devices`
+	#[serde(default)] pub host: Option<String>,
+	/// The name of the output device
+	#[serde(default)] pub device: Option<String>,
+	#[serde(default)] pub sample_hz: Option<u32>,
+	/// The buffer size in frames. Since the audio backend
+	/// (CPAL 0.10) does not support choosing it, requests
+	/// that specify it are rejected for now.
+	#[serde(default)] pub buffer_size: Option<u32>
+}
+
+/// The engine methods exposed via JSON-RPC
+#[rpc]
+pub trait EngineServiceRpc {
+	/// Lists the available hosts along with their
+	/// output devices and supported formats, which
+	/// the speaker engine can be restarted on.
+	#[rpc(name = "engine.devices")]
+	fn devices(&self) -> RpcResult<Vec<RpcHost>>;
+	
+	/// Resumes the engine's output. The file engine
+	/// starts paused, thus rendering begins here.
+	#[rpc(name = "engine.play")]
+	fn play(&self) -> RpcResult<()>;
+	
+	/// Pauses the engine's output
+	#[rpc(name = "engine.pause")]
+	fn pause(&self) -> RpcResult<()>;
+	
+	/// Shuts the engine down, after which it can no
+	/// longer be resumed (only the speaker engine
+	/// can be restarted, see `engine.restart`). The
+	/// speaker engine releases the output device, but
+	/// leaves an idle thread behind until the server
+	/// exits.
+	#[rpc(name = "engine.stop")]
+	fn stop(&self) -> RpcResult<()>;
+	
+	/// Fetches the playback state and output
+	/// format of the engine
+	#[rpc(name = "engine.status")]
+	fn status(&self) -> RpcResult<RpcEngineStatus>;
+	
+	/// Restarts the speaker engine on another output
+	/// (or the same one after it has been stopped),
+	/// keeping the graph. Other engines can not be
+	/// restarted. Returns the new status.
+	#[rpc(name = "engine.restart")]
+	fn restart(&self, output: RpcSpeakerOutput) -> RpcResult<RpcEngineStatus>;
+	
+	/// Fetches runtime statistics of the engine
+	#[rpc(name = "engine.stats")]
+	fn stats(&self) -> RpcResult<RpcEngineStats>;
+}
+
+/// The engine service client generated by
+/// jsonrpc_derive.
+pub type EngineServiceClient = gen_client::Client;
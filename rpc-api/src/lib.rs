@@ -3,6 +3,10 @@ use jsonrpc_derive::rpc;
 use serde::{Serialize, Deserialize};
 use std::collections::HashMap;
 
+pub mod engine;
+
+pub use engine::{RpcFormat, RpcDevice, RpcHost, RpcEngineStats, RpcEngineStatus, RpcPlaybackState, RpcSpeakerOutput, EngineServiceRpc, EngineServiceClient};
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "type")]
 /// A node in the audio graph.
@@ -20,7 +24,14 @@ pub enum RpcNode {
 	#[serde(rename_all = "camelCase")]
 	File {
 		file_path: String,
-		#[serde(default)] paused: bool
+		#[serde(default)] paused: bool,
+		/// The playback position (may be set to start playing from a position)
+		#[serde(default)] position_secs: f64,
+		/// The length of the file (ignored when creating nodes)
+		#[serde(default)] duration_secs: Option<f64>,
+		/// The error that stopped decoding prematurely (ignored when creating nodes)
+		#[serde(default)] error: Option<String>,
+		#[serde(default)] resample_quality: RpcResampleQuality
 	},
 	/// A command source
 	#[serde(rename_all = "camelCase")]
@@ -29,7 +40,8 @@ pub enum RpcNode {
 		#[serde(default)] args: Vec<String>,
 		sample_hz: f64,
 		takes_input: bool,
-		paused: bool
+		paused: bool,
+		#[serde(default)] resample_quality: RpcResampleQuality
 	},
 	/// A dynamically dispatched source
 	DynSource,
@@ -51,7 +63,11 @@ pub enum RpcNode {
 	Other
 }
 
+/// A stable node identifier, which remains valid
+/// when other nodes are removed and is never reused.
 pub type RpcNodeIndex = usize;
+/// A stable edge identifier, which remains valid
+/// when other edges are removed and is never reused.
 pub type RpcEdgeIndex = usize;
 
 #[derive(Serialize, Deserialize, Copy, Clone, Debug)]
@@ -67,14 +83,165 @@ impl RpcEdge {
 	}
 }
 
+#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
+#[serde(untagged)]
+/// Refers to an edge either by its index
+/// or by the nodes it connects.
+pub enum RpcEdgeSelector {
+	Index(RpcEdgeIndex),
+	Between(RpcEdge)
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(untagged)]
+/// Refers to a node within a batch, either by its
+/// index or by a placeholder for a node added by a
+/// preceding operation of the same batch.
+pub enum RpcNodeRef {
+	Index(RpcNodeIndex),
+	Placeholder(String)
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "op", rename_all = "camelCase")]
+/// A single operation within a batch.
+pub enum RpcGraphOp {
+	/// Adds a node, which subsequent operations
+	/// may refer to using the placeholder
+	#[serde(rename_all = "camelCase")]
+	AddNode {
+		node: RpcNode,
+		#[serde(default)] placeholder: Option<String>
+	},
+	RemoveNode {
+		node: RpcNodeRef
+	},
+	ReplaceNode {
+		node: RpcNodeRef,
+		replacement: RpcNode
+	},
+	AddEdge {
+		src: RpcNodeRef,
+		dest: RpcNodeRef
+	},
+	RemoveEdge {
+		edge: RpcEdgeIndex
+	},
+	SetMaster {
+		node: RpcNodeRef
+	}
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+/// The result of a successfully applied batch.
+pub struct RpcBatchResult {
+	/// The indices of the nodes added by the batch
+	pub placeholders: HashMap<String, RpcNodeIndex>,
+	/// The indices of the edges added by the batch,
+	/// in the order of the operations
+	pub edges: Vec<RpcEdgeIndex>
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 /// The audio graph.
 pub struct RpcGraph {
 	pub nodes: HashMap<RpcNodeIndex, RpcNode>,
-	pub edges: Vec<RpcEdge>,
+	/// The edges by their (stable) indices
+	pub edges: HashMap<RpcEdgeIndex, RpcEdge>,
+	/// The names of the named nodes
+	#[serde(default)] pub names: HashMap<RpcNodeIndex, String>,
 	pub master: Option<RpcNodeIndex>
 }
 
+/// The version of the project file format
+/// written by `audioGraph.save`.
+pub const PROJECT_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug)]
+/// A saved audio graph, including the state of its
+/// sources (e.g. whether they are paused and their
+/// playback positions).
+pub struct RpcProject {
+	pub version: u32,
+	pub graph: RpcGraph
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+/// A node that could not be restored from a project
+/// file and thus was replaced by an empty node.
+pub struct RpcRestoreFailure {
+	/// The index of the node within the project file
+	pub node: RpcNodeIndex,
+	pub error: String
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+/// The result of loading a project file.
+pub struct RpcLoadResult {
+	/// Maps the node indices within the project
+	/// file to the indices of the restored nodes
+	pub nodes: HashMap<RpcNodeIndex, RpcNodeIndex>,
+	pub failures: Vec<RpcRestoreFailure>,
+	/// The indices (within the project file) of edges that
+	/// were skipped since they would create a cycle or
+	/// connect nodes that do not exist
+	pub skipped_edges: Vec<RpcEdgeIndex>
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+/// The descriptions of the undoable and redoable
+/// edits, starting with the most recent ones.
+pub struct RpcHistory {
+	pub undo: Vec<String>,
+	pub redo: Vec<String>
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+#[serde(tag = "type")]
+/// The interpolation used when converting
+/// a source to the engine's sample rate.
+pub enum RpcResampleQuality {
+	/// Fast, but prone to aliasing
+	Linear,
+	/// Four-point cubic interpolation
+	Cubic,
+	/// Windowed sinc interpolation
+	Sinc {
+		#[serde(default = "default_sinc_taps")] taps: usize
+	}
+}
+
+impl Default for RpcResampleQuality {
+	fn default() -> RpcResampleQuality { RpcResampleQuality::Linear }
+}
+
+fn default_sinc_taps() -> usize { 64 }
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+/// An embedded picture, such as a front cover.
+pub struct RpcCoverArt {
+	pub mime_type: String,
+	pub description: String,
+	/// The base64-encoded image data
+	pub data: String
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+/// Descriptive metadata about a track.
+pub struct RpcTrackMetadata {
+	pub title: Option<String>,
+	pub artist: Option<String>,
+	pub album: Option<String>,
+	pub album_artist: Option<String>,
+	pub track_number: Option<String>,
+	pub date: Option<String>,
+	pub genre: Option<String>,
+	pub cover_art: Option<RpcCoverArt>
+}
+
 /// The audio graph methods exposed via JSON-RPC
 #[rpc]
 pub trait AudioGraphServiceRpc {
@@ -82,7 +249,10 @@ pub trait AudioGraphServiceRpc {
 	#[rpc(name = "audioGraph.get")]
 	fn get(&self) -> RpcResult<RpcGraph>;
 	
-	/// Adds a node to the graph, returning its index
+	/// Adds a node to the graph, returning its index.
+	/// If a file's format is not recognized, the error's
+	/// data holds the `filePath`, the `probed` formats
+	/// and the `extension`.
 	#[rpc(name = "audioGraph.addNode")]
 	fn add_node(&self, node: RpcNode) -> RpcResult<RpcNodeIndex>;
 	
@@ -96,11 +266,76 @@ pub trait AudioGraphServiceRpc {
 	#[rpc(name = "audioGraph.replaceNode")]
 	fn replace_node(&self, index: RpcNodeIndex, node: RpcNode) -> RpcResult<()>;
 	
-	/// Adds an edge to the graph
+	/// Seeks to a position (in seconds) within a seekable
+	/// node, such as a file source. Like pausing the engine,
+	/// this changes the playback state rather than the graph
+	/// and thus is not recorded in the undo history.
+	#[rpc(name = "audioGraph.seek")]
+	fn seek(&self, index: RpcNodeIndex, secs: f64) -> RpcResult<()>;
+	
+	/// Fetches the track metadata (tags and cover art)
+	/// of a file node
+	#[rpc(name = "audioGraph.getNodeMetadata")]
+	fn get_node_metadata(&self, index: RpcNodeIndex) -> RpcResult<RpcTrackMetadata>;
+	
+	/// Adds an edge to the graph, returning its index.
+	/// Edge indices remain valid until the edge is removed.
 	#[rpc(name = "audioGraph.addEdge")]
 	fn add_edge(&self, edge: RpcEdge) -> RpcResult<RpcEdgeIndex>;
 	
-	// TODO: removeEdge
+	/// Removes an edge (given either by its index
+	/// or by its endpoints) from the graph
+	#[rpc(name = "audioGraph.removeEdge")]
+	fn remove_edge(&self, edge: RpcEdgeSelector) -> RpcResult<()>;
+	
+	/// Applies a list of operations atomically, i.e. either
+	/// all or (if any of them fails) none of them are applied
+	#[rpc(name = "audioGraph.applyBatch")]
+	fn apply_batch(&self, ops: Vec<RpcGraphOp>) -> RpcResult<RpcBatchResult>;
+	
+	/// Names (or, given null, unnames) a node. Like
+	/// other edits, this is recorded in the undo history.
+	#[rpc(name = "audioGraph.setNodeName")]
+	fn set_node_name(&self, index: RpcNodeIndex, name: Option<String>) -> RpcResult<()>;
+	
+	/// Saves the graph to a JSON project file
+	#[rpc(name = "audioGraph.save")]
+	fn save(&self, path: String) -> RpcResult<()>;
+	
+	/// Replaces the graph with the one saved in a project
+	/// file. Nodes that can not be restored (e.g. since a
+	/// file has been moved) are replaced by empty nodes
+	/// and reported instead of failing the entire load.
+	/// Loading discards the undo history.
+	#[rpc(name = "audioGraph.load")]
+	fn load(&self, path: String) -> RpcResult<RpcLoadResult>;
+	
+	/// Reverts the most recent edit made through this
+	/// service, returning its description. Restored nodes
+	/// and edges keep their previous indices.
+	/// 
+	/// The history covers the structure of the graph and the
+	/// parameters of its nodes (including their names), but
+	/// not playback positions. To restore them, the history
+	/// keeps removed and replaced nodes alive, i.e. their
+	/// files remain open and their commands keep running
+	/// until the history is cleared.
+	#[rpc(name = "audioGraph.undo")]
+	fn undo(&self) -> RpcResult<String>;
+	
+	/// Reapplies the most recently undone edit,
+	/// returning its description
+	#[rpc(name = "audioGraph.redo")]
+	fn redo(&self) -> RpcResult<String>;
+	
+	/// Fetches the undoable and redoable edits
+	#[rpc(name = "audioGraph.history")]
+	fn history(&self) -> RpcResult<RpcHistory>;
+	
+	/// Discards the undo history, releasing the
+	/// removed and replaced nodes held by it
+	#[rpc(name = "audioGraph.clearHistory")]
+	fn clear_history(&self) -> RpcResult<()>;
 }
 
 /// The audio graph service client generated by